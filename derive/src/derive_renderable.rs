@@ -1,16 +1,58 @@
 use crate::inline_parse::{parse_inline, Segment, Substitutable};
+use crate::locations::{parse_at, parse_str_at, AtOffset, InAttribute, Location, SourceMap};
 use crate::{TemplateDerivation, TemplateSource};
-use anyhow::{anyhow, Context, Error};
+use anyhow::{anyhow, bail, Context, Error};
 use kuchiki::iter::Siblings;
 use kuchiki::{ElementData, ExpandedName, NodeData, NodeRef};
 use log::*;
-use proc_macro2::TokenStream as TokenStream2;
+use proc_macro2::{Span, TokenStream as TokenStream2};
 use quote::quote;
 use quote::TokenStreamExt;
 use syn::{parse_quote, Pat, Token};
 
-#[derive(Default, Debug)]
-struct Walker;
+/// Attributes whose presence alone is significant, so may be given a `bool`.
+const BOOLEAN_ATTRIBUTES: &[&str] = &[
+    "allowfullscreen",
+    "async",
+    "autofocus",
+    "autoplay",
+    "checked",
+    "controls",
+    "default",
+    "defer",
+    "disabled",
+    "formnovalidate",
+    "hidden",
+    "inert",
+    "ismap",
+    "itemscope",
+    "loop",
+    "multiple",
+    "muted",
+    "nomodule",
+    "novalidate",
+    "open",
+    "playsinline",
+    "readonly",
+    "required",
+    "reversed",
+    "selected",
+];
+
+/// A pseudo-element that only ever renders its content.
+const BLOCK_ELEMENT: &str = "weft:block";
+
+/// Elements whose content the HTML parser treats as raw text, rather than markup.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+#[derive(Debug, Clone, Copy)]
+struct Walker<'a> {
+    keep_comments: bool,
+    // Set within an element with `weft-inline-disable`, where `{{ … }}` is literal text.
+    inline_disabled: bool,
+    sources: &'a SourceMap,
+    span: Span,
+}
 
 #[derive(Default, Debug)]
 struct Attribute {
@@ -22,11 +64,75 @@ struct Attribute {
 struct Directives {
     replacement: Option<syn::Expr>,
     content: Option<syn::Expr>,
-    conditional: Option<syn::Expr>,
+    branch: Branch,
+    matched: Option<syn::Expr>,
+    case: Option<CaseDecl>,
     iterator: Option<IteratorDecl>,
+    loop_info: Option<syn::Ident>,
+    bindings: Vec<LetDecl>,
+    strip: Strip,
+    dynamic_attrs: Option<syn::Expr>,
     plain_attrs: Vec<Attribute>,
 }
 
+/// How an element participates in a `weft-if` / `weft-else` chain.
+#[derive(Default, Debug)]
+enum Branch {
+    #[default]
+    Always,
+    If(syn::Expr),
+    ElseIf(syn::Expr),
+    Else,
+}
+
+/// Whether an element's own tags are omitted, leaving only its content.
+#[derive(Default, Debug)]
+enum Strip {
+    #[default]
+    Never,
+    Always,
+    When(syn::Expr),
+}
+
+/// The parts of an element needed to write its start and end tags.
+struct Tag<'a> {
+    localname: &'a str,
+    attrs: &'a [Attribute],
+    dynamic_attrs: Option<&'a syn::Expr>,
+    strip: &'a Strip,
+}
+
+/// The code generated for an element, along with how it relates to its siblings.
+#[derive(Debug)]
+struct ElementCode {
+    branch: Branch,
+    case: Option<CaseDecl>,
+    tokens: TokenStream2,
+}
+
+/// A `weft-case` pattern, with an optional guard, eg: `Some(x) if x > 3`.
+#[derive(Debug)]
+struct CaseDecl {
+    pattern: syn::Pat,
+    guard: Option<(Token![if], syn::Expr)>,
+}
+
+/// Accumulates the branches of a conditional chain whilst walking siblings.
+#[derive(Default, Debug)]
+struct Chain {
+    branches: Option<TokenStream2>,
+    // Whitespace and comments between branches, written after the chain.
+    deferred: TokenStream2,
+}
+
+/// A `weft-let` binding, eg: `year = self.year.display()`.
+#[derive(Debug)]
+struct LetDecl {
+    pattern: syn::Pat,
+    eq: Token![=],
+    expr: syn::Expr,
+}
+
 #[derive(Debug)]
 struct IteratorDecl {
     pattern: syn::Pat,
@@ -34,9 +140,20 @@ struct IteratorDecl {
     expr: syn::Expr,
 }
 
-fn render_to_fn(src: &TemplateDerivation, nodes: NodeRef) -> Result<TokenStream2, Error> {
-    let walker = Walker;
-    let impl_body = walker.children(nodes.children())?;
+fn render_to_fn(
+    src: &TemplateDerivation,
+    nodes: Vec<NodeRef>,
+    sources: &SourceMap,
+    name: &syn::Ident,
+) -> Result<TokenStream2, Error> {
+    let name = name.to_string();
+    let walker = Walker {
+        keep_comments: src.keep_comments,
+        inline_disabled: false,
+        sources,
+        span: src.span,
+    };
+    let impl_body = walker.children(nodes)?;
     let include_deps = if let TemplateSource::Path(path) = &src.template_source {
         std::fs::metadata(path).with_context(|| format!("Checking for file: {:?}", path))?;
 
@@ -51,22 +168,27 @@ fn render_to_fn(src: &TemplateDerivation, nodes: NodeRef) -> Result<TokenStream2
     };
 
     Ok(quote! {
-            fn render_to(&self, mut __weft_target: &mut impl ::weft::RenderTarget) -> Result<(), ::std::io::Error> {
+            fn render_to(&self, mut __weft_target: &mut impl ::weft::RenderTarget) -> Result<(), ::weft::Error> {
                 use ::weft::prelude::*;
                 #include_deps;
-                #impl_body;
-                Ok(())
+                // Expressions may use `?` on any error type, via `ExprError`.
+                (|| -> Result<(), ::weft::ExprError> {
+                    #impl_body;
+                    Ok(())
+                })()
+                .map_err(|err| ::weft::Error::from(err).in_template(#name))
             }
     })
 }
 
 pub(crate) fn derive_impl(
     src: &TemplateDerivation,
-    nodes: NodeRef,
+    nodes: Vec<NodeRef>,
+    sources: &SourceMap,
     mut item: syn::DeriveInput,
 ) -> Result<TokenStream2, Error> {
     info!("Deriving implementation for {}", item.ident);
-    let render_to_fn_impl = render_to_fn(src, nodes)?;
+    let render_to_fn_impl = render_to_fn(src, nodes, sources, &item.ident)?;
 
     info!("Generics before: {:#?}", item.generics);
     let bounds = item
@@ -99,7 +221,7 @@ pub(crate) fn derive_impl(
     Ok(res)
 }
 
-impl Walker {
+impl Walker<'_> {
     fn dom(&self, node: NodeRef) -> Result<TokenStream2, Error> {
         match node.data() {
             NodeData::Document(_) => {
@@ -107,24 +229,32 @@ impl Walker {
                 trace!("Document => {}", ts);
                 Ok(ts)
             }
-            NodeData::Element(data) => {
-                trace!("Element: {:?}", data);
-                let ts = self.element(data, node.children())?;
-                trace!("Element => {}", ts);
-                Ok(ts)
-            }
+            NodeData::Element(_) => self.children(std::iter::once(node)),
             NodeData::Text(ref contents) => {
-                let ts = self.text(&contents.borrow())?;
+                let raw = node
+                    .parent()
+                    .and_then(|p| {
+                        p.as_element()
+                            .map(|e| RAW_TEXT_ELEMENTS.contains(&&*e.name.local))
+                    })
+                    .unwrap_or(false);
+                let contents = contents.borrow();
+                let ts = self.text(&contents, raw)?;
                 trace!("Text => {}", ts);
                 Ok(ts)
             }
-            NodeData::Doctype { .. } => {
-                debug!("Ignoring doctype: children: {:?}", node.children().count());
-                Ok(TokenStream2::default())
+            NodeData::Doctype(ref doctype) => {
+                trace!("Doctype: {:?}", doctype.name);
+                Ok(quote!(__weft_target.doctype()?;))
             }
-            NodeData::Comment { .. } => {
-                debug!("Ignoring comment: children: {:?}", node.children().count());
-                Ok(TokenStream2::default())
+            NodeData::Comment(ref contents) => {
+                if self.keep_comments {
+                    let contents = contents.borrow().to_string();
+                    Ok(quote!(__weft_target.comment(#contents)?;))
+                } else {
+                    debug!("Ignoring comment: {:?}", contents);
+                    Ok(TokenStream2::default())
+                }
             }
             NodeData::ProcessingInstruction { .. } => {
                 debug!(
@@ -140,58 +270,265 @@ impl Walker {
         }
     }
 
-    fn children(&self, nodes: Siblings) -> Result<TokenStream2, Error> {
+    fn children(&self, nodes: impl IntoIterator<Item = NodeRef>) -> Result<TokenStream2, Error> {
+        let nodes = nodes.into_iter().collect::<Vec<_>>();
         let mut res = TokenStream2::new();
-        for child in nodes {
-            res.extend(self.dom(child)?);
+        let mut chain = Chain::default();
+        let mut consumed_fallback = None;
+        for (idx, child) in nodes.iter().enumerate() {
+            if consumed_fallback == Some(idx) {
+                continue;
+            }
+            let consumed = self.sibling(child, &nodes[idx + 1..], &mut chain, &mut res);
+            if let Some(offset) = self.located(child, consumed)? {
+                consumed_fallback = Some(idx + 1 + offset);
+            }
         }
+        chain.finish_into(&mut res);
 
         Ok(res)
     }
 
-    fn element(&self, data: &ElementData, children: Siblings) -> Result<TokenStream2, Error> {
+    /// Writes the code for `child` into `res` or the current `chain`. Returns
+    /// the offset within `following` of a `weft-for-empty` fallback that has
+    /// been consumed along with it.
+    fn sibling(
+        &self,
+        child: &NodeRef,
+        following: &[NodeRef],
+        chain: &mut Chain,
+        res: &mut TokenStream2,
+    ) -> Result<Option<usize>, Error> {
+        let data = match child.as_element() {
+            Some(data) => data,
+            None => {
+                if chain.branches.is_some() && is_ignorable(child) {
+                    chain.deferred.extend(self.dom(child.clone())?);
+                } else {
+                    chain.finish_into(res);
+                    res.extend(self.dom(child.clone())?);
+                }
+                return Ok(None);
+            }
+        };
+
+        trace!("Element: {:?}", data);
+        if has_attr(data, "weft-for-empty") {
+            bail!("weft-for-empty must follow an element with weft-for");
+        }
+        let (consumed, empty) = match self.empty_fallback(data, following)? {
+            Some((offset, code)) => (Some(offset), Some(code)),
+            None => (None, None),
+        };
+        let ElementCode {
+            branch,
+            case,
+            tokens: ts,
+        } = self.element(child, data, empty)?;
+        trace!("Element => {:?} {}", branch, ts);
+        if case.is_some() {
+            bail!("weft-case must be used on a child of an element with weft-match");
+        }
+        match branch {
+            Branch::Always => {
+                chain.finish_into(res);
+                res.extend(ts);
+            }
+            Branch::If(test) => {
+                chain.finish_into(res);
+                chain.branches = Some(quote!(if #test { #ts }));
+            }
+            Branch::ElseIf(test) => chain.extend(quote!(else if #test { #ts }))?,
+            Branch::Else => {
+                chain.extend(quote!(else { #ts }))?;
+                chain.finish_into(res);
+            }
+        }
+        Ok(consumed)
+    }
+
+    /// Adds the template location of `node` (or of the attribute at fault)
+    /// to an error, unless a more specific location was already given.
+    fn located<T>(&self, node: &NodeRef, res: Result<T, Error>) -> Result<T, Error> {
+        res.map_err(|err| {
+            if err.downcast_ref::<Location>().is_some() {
+                return err;
+            }
+            let attribute = err.downcast_ref::<InAttribute>().map(|a| a.0.clone());
+            let within = err.downcast_ref::<AtOffset>().map(|o| o.0);
+            match self.sources.locate(node, attribute.as_deref(), within) {
+                Some(location) => err.context(location),
+                None => err,
+            }
+        })
+    }
+
+    /// For an element with `weft-for`, finds the code for an immediately
+    /// following sibling with `weft-for-empty`, and its offset in `following`.
+    fn empty_fallback(
+        &self,
+        data: &ElementData,
+        following: &[NodeRef],
+    ) -> Result<Option<(usize, TokenStream2)>, Error> {
+        if !has_attr(data, "weft-for") {
+            return Ok(None);
+        }
+        let next = following
+            .iter()
+            .enumerate()
+            .find(|(_, node)| !is_ignorable(node));
+        let (offset, node, fallback) = match next {
+            Some((offset, node)) => match node.as_element() {
+                Some(fallback) if has_attr(fallback, "weft-for-empty") => (offset, node, fallback),
+                _ => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+
+        let code = self.element(node, fallback, None)?;
+        if !matches!(code.branch, Branch::Always) || code.case.is_some() {
+            bail!("weft-for-empty cannot be combined with weft-if, weft-else or weft-case");
+        }
+        Ok(Some((offset, code.tokens)))
+    }
+
+    fn element(
+        &self,
+        node: &NodeRef,
+        data: &ElementData,
+        empty: Option<TokenStream2>,
+    ) -> Result<ElementCode, Error> {
+        let children = node.children();
         let localname = data.name.local.to_string();
         trace!("Start Element {:?}", data);
 
-        let directive = Directives::parse_from_attrs(&data.attributes.borrow())?;
+        if !self.inline_disabled && has_attr(data, "weft-inline-disable") {
+            let walker = Walker {
+                inline_disabled: true,
+                ..*self
+            };
+            return walker.element(node, data, empty);
+        }
+
+        let directive = Directives::parse_from_attrs(
+            &data.attributes.borrow(),
+            !self.inline_disabled,
+            self.span,
+        )?;
+        // Whether an element is void is left to the target at runtime (see
+        // `weft::QName::is_void`); we only say when there is no content.
+        let is_empty = node.first_child().is_none();
+        let strip = if localname == BLOCK_ELEMENT {
+            Strip::Always
+        } else {
+            directive.strip
+        };
+        let tag = Tag {
+            localname: &localname,
+            attrs: &directive.plain_attrs,
+            dynamic_attrs: directive.dynamic_attrs.as_ref(),
+            strip: &strip,
+        };
         let res = if let Some(repl) = directive.replacement {
-            quote!(#repl.render_to(&mut __weft_target)?;)
+            quote!((#repl).render_to(&mut __weft_target)?;)
         } else if let Some(content) = directive.content {
-            let content = quote!(#content.render_to(&mut __weft_target)?;);
-            self.emit_element(&localname, &directive.plain_attrs, content)
+            if directive.matched.is_some() {
+                bail!("weft-content and weft-match cannot be used together");
+            }
+            let content = quote!((#content).render_to(&mut __weft_target)?;);
+            self.emit_element(&tag, content)
+        } else if let Some(matched) = directive.matched.as_ref() {
+            let content = self.match_arms(matched, children)?;
+            self.emit_element(&tag, content)
+        } else if is_empty {
+            self.emit_void_element(&tag)
         } else {
             let content = self.children(children)?;
-            self.emit_element(&localname, &directive.plain_attrs, content)
+            self.emit_element(&tag, content)
         };
 
-        let res = if let Some(iter) = directive.iterator {
-            quote!(for #iter { #res }; )
-        } else {
+        let res = if directive.bindings.is_empty() {
             res
+        } else {
+            let bindings = &directive.bindings;
+            quote!({ #(let #bindings;)* #res })
         };
 
-        let res = if let Some(test) = directive.conditional {
-            quote!(if #test { #res }; )
-        } else {
-            res
+        let head = match (directive.iterator, directive.loop_info) {
+            (Some(iter), None) => Some(quote!(#iter)),
+            (Some(IteratorDecl { pattern, in_, expr }), Some(info)) => {
+                Some(quote!((#pattern, #info) #in_ ::weft::LoopIter::new(#expr)))
+            }
+            (None, Some(_)) => bail!("weft-loop may only be used alongside weft-for"),
+            (None, None) => None,
         };
+
+        let res = match (head, empty) {
+            (Some(head), None) => quote!(for #head { #res }; ),
+            (Some(head), Some(empty)) => quote!({
+                let mut __weft_empty = true;
+                for #head {
+                    __weft_empty = false;
+                    #res
+                }
+                if __weft_empty { #empty }
+            }),
+            (None, _) => res,
+        };
+
         trace!("End Element {:?}", data);
 
-        Ok(res)
+        Ok(ElementCode {
+            branch: directive.branch,
+            case: directive.case,
+            tokens: res,
+        })
     }
-    fn text(&self, contents: &str) -> Result<TokenStream2, Error> {
+
+    fn match_arms(&self, matched: &syn::Expr, children: Siblings) -> Result<TokenStream2, Error> {
+        let mut arms = TokenStream2::new();
+        for child in children {
+            let data = match child.as_element() {
+                Some(data) => data,
+                None if is_ignorable(&child) => continue,
+                None => bail!("Only elements with weft-case may be children of weft-match"),
+            };
+            if has_attr(data, "weft-for-empty") {
+                bail!("weft-for-empty must follow an element with weft-for");
+            }
+            let code = self.located(&child, self.element(&child, data, None))?;
+            let case = code
+                .case
+                .ok_or_else(|| anyhow!("Children of weft-match must have weft-case"))?;
+            if !matches!(code.branch, Branch::Always) {
+                bail!("weft-case cannot be combined with weft-if or weft-else; use a guard");
+            }
+            let tokens = code.tokens;
+            arms.extend(quote!(#case => { #tokens }));
+        }
+        Ok(quote!(match #matched { #arms }))
+    }
+    fn text(&self, contents: &str, raw: bool) -> Result<TokenStream2, Error> {
         let mut result = TokenStream2::new();
         let cdata = contents.to_string();
         trace!("Text {:?}", cdata);
-        let parsed = parse_inline(&cdata)?;
+        let parsed = if self.inline_disabled {
+            Substitutable::literal(cdata)
+        } else {
+            parse_inline(&cdata, self.span)?
+        };
         for segment in parsed.children() {
             match segment {
+                Segment::Literal(cdata) if raw => {
+                    let chunk = quote!(__weft_target.raw_text(#cdata)?;);
+                    result.extend(chunk);
+                }
                 Segment::Literal(cdata) => {
                     let chunk = quote!(__weft_target.text(#cdata)?;);
                     result.extend(chunk);
                 }
                 Segment::Expr(expr) => {
-                    let chunk = quote!(#expr.render_to(&mut __weft_target)?;);
+                    let chunk = quote!((#expr).render_to(&mut __weft_target)?;);
                     result.extend(chunk);
                 }
             }
@@ -199,60 +536,221 @@ impl Walker {
         Ok(result)
     }
 
-    fn emit_element(
-        &self,
-        localname: &str,
-        attrs: &[Attribute],
-        content: TokenStream2,
-    ) -> proc_macro2::TokenStream {
-        let attrs_q = quote!(&[#(&#attrs),*]);
-        let mut statements = TokenStream2::new();
-        statements.extend(quote!(
-            __weft_target.start_element_attrs(#localname.into(), #attrs_q)?;
-        ));
-
-        statements.extend(content);
-
-        statements.extend(quote!(
+    fn emit_element(&self, tag: &Tag, content: TokenStream2) -> proc_macro2::TokenStream {
+        let localname = tag.localname;
+        let open = self.emit_open(quote!(start_element_attrs), tag);
+        let close = quote!(
             __weft_target.end_element(#localname.into())?;
-        ));
-        statements
+        );
+
+        match tag.strip {
+            Strip::Never => quote!(#open #content #close),
+            Strip::Always => content,
+            Strip::When(test) => quote!({
+                let __weft_strip: bool = #test;
+                if !__weft_strip { #open }
+                #content
+                if !__weft_strip { #close }
+            }),
+        }
+    }
+
+    fn emit_void_element(&self, tag: &Tag) -> TokenStream2 {
+        let open = self.emit_open(quote!(void_element_attrs), tag);
+        match tag.strip {
+            Strip::Never => open,
+            Strip::Always => TokenStream2::new(),
+            Strip::When(test) => quote!(if !(#test) { #open }),
+        }
+    }
+
+    fn emit_open(&self, method: TokenStream2, tag: &Tag) -> TokenStream2 {
+        let Tag {
+            localname,
+            attrs,
+            dynamic_attrs,
+            ..
+        } = *tag;
+        if dynamic_attrs.is_none() && attrs.iter().all(|a| a.condition().is_none()) {
+            let attrs_q = quote!(&[#(&#attrs),*]);
+            return quote!(
+                __weft_target.#method(#localname.into(), #attrs_q)?;
+            );
+        }
+
+        // Some attributes may be omitted, so we collect those present at runtime.
+        let optional_attrs = attrs.iter().map(|a| match a.condition() {
+            Some(test) => {
+                let key_name = &a.name;
+                quote!(if #test {
+                    Some(::weft::AttrPair::new(::weft::QName::from(#key_name), "".into()))
+                } else {
+                    None
+                })
+            }
+            None => quote!(Some(#a)),
+        });
+        let merge_dynamic = dynamic_attrs
+            .map(|expr| quote!(let __weft_attrs = ::weft::merge_attrs(__weft_attrs, #expr)?;));
+        quote!({
+            let __weft_attrs = ::std::iter::IntoIterator::into_iter([#(#optional_attrs),*])
+                .flatten()
+                .collect::<Vec<_>>();
+            #merge_dynamic
+            let __weft_attrs = __weft_attrs.iter().collect::<Vec<_>>();
+            __weft_target.#method(#localname.into(), &__weft_attrs)?;
+        })
     }
 }
 
 impl Directives {
-    fn parse_from_attrs(attrs: &kuchiki::Attributes) -> Result<Self, Error> {
+    fn parse_from_attrs(
+        attrs: &kuchiki::Attributes,
+        inline: bool,
+        span: Span,
+    ) -> Result<Self, Error> {
         let mut it = Self::default();
         for (name, value) in attrs.map.iter() {
-            match &*name.local {
-                "weft-replace" => {
-                    let replacement = syn::parse_str(&value.value)?;
-                    it.replacement = Some(replacement)
-                }
-                "weft-content" => {
-                    let content = syn::parse_str(&value.value)?;
-                    it.content = Some(content)
-                }
-                "weft-if" => {
-                    let test = syn::parse_str(&value.value)?;
-                    it.conditional = Some(test)
-                }
-                "weft-for" => {
-                    let iterator = syn::parse_str(&value.value)?;
-                    it.iterator = Some(iterator)
-                }
-                _ => it.plain_attrs.push(Attribute::parse(name, value)?),
-            }
+            it.parse_attr(name, value, inline, span)
+                .with_context(|| InAttribute(name.local.to_string()))?;
         }
 
         Ok(it)
     }
+
+    fn parse_attr(
+        &mut self,
+        name: &ExpandedName,
+        value: &kuchiki::Attribute,
+        inline: bool,
+        span: Span,
+    ) -> Result<(), Error> {
+        match &*name.local {
+            "weft-replace" => {
+                let replacement = parse_str_at(&value.value, span)?;
+                self.replacement = Some(replacement)
+            }
+            "weft-content" => {
+                let content = parse_str_at(&value.value, span)?;
+                self.content = Some(content)
+            }
+            "weft-if" => {
+                let test = parse_str_at(&value.value, span)?;
+                self.set_branch(Branch::If(test))?
+            }
+            "weft-else-if" => {
+                let test = parse_str_at(&value.value, span)?;
+                self.set_branch(Branch::ElseIf(test))?
+            }
+            "weft-else" => self.set_branch(Branch::Else)?,
+            "weft-for-empty" => {}
+            "weft-strip" if value.value.trim().is_empty() => self.strip = Strip::Always,
+            "weft-strip" => {
+                let test = parse_str_at(&value.value, span)?;
+                self.strip = Strip::When(test)
+            }
+            "weft-loop" => {
+                let info = parse_str_at(&value.value, span)?;
+                self.loop_info = Some(info)
+            }
+            "weft-let" => {
+                let parser = syn::punctuated::Punctuated::<LetDecl, Token![;]>::parse_terminated;
+                let bindings = parse_at(parser, &value.value, span)?;
+                self.bindings.extend(bindings)
+            }
+            "weft-match" => {
+                let matched = parse_str_at(&value.value, span)?;
+                self.matched = Some(matched)
+            }
+            "weft-case" => {
+                let case = parse_str_at(&value.value, span)?;
+                self.case = Some(case)
+            }
+            "weft-for" => {
+                let iterator = parse_str_at(&value.value, span)?;
+                self.iterator = Some(iterator)
+            }
+            "weft-attrs" => {
+                let attrs = parse_str_at(&value.value, span)?;
+                self.dynamic_attrs = Some(attrs)
+            }
+            "weft-inline-disable" | "weft-def" => {}
+            _ => self
+                .plain_attrs
+                .push(Attribute::parse(name, value, inline, span)?),
+        }
+
+        Ok(())
+    }
+
+    fn set_branch(&mut self, branch: Branch) -> Result<(), Error> {
+        if !matches!(self.branch, Branch::Always) {
+            bail!("Only one of weft-if, weft-else-if or weft-else may be used on an element");
+        }
+        self.branch = branch;
+        Ok(())
+    }
+}
+
+impl Chain {
+    fn extend(&mut self, branch: TokenStream2) -> Result<(), Error> {
+        match self.branches {
+            Some(ref mut branches) => {
+                branches.extend(branch);
+                Ok(())
+            }
+            None => bail!("weft-else and weft-else-if must follow an element with weft-if"),
+        }
+    }
+
+    fn finish_into(&mut self, res: &mut TokenStream2) {
+        if let Some(branches) = self.branches.take() {
+            res.extend(quote!(#branches;));
+        }
+        res.extend(std::mem::take(&mut self.deferred));
+    }
+}
+
+fn has_attr(data: &ElementData, name: &str) -> bool {
+    data.attributes.borrow().contains(name)
+}
+
+/// Whitespace and comments may appear between the branches of a conditional.
+fn is_ignorable(node: &NodeRef) -> bool {
+    match node.data() {
+        NodeData::Text(ref contents) => contents.borrow().trim().is_empty(),
+        NodeData::Comment(_) => true,
+        _ => false,
+    }
 }
 
 impl Attribute {
-    fn parse(name: &ExpandedName, value: &kuchiki::Attribute) -> Result<Self, Error> {
+    /// For boolean attributes given a single expression, such as
+    /// `disabled="{{ self.locked }}"`, returns the condition under which the
+    /// attribute is present.
+    fn condition(&self) -> Option<syn::Expr> {
+        if !BOOLEAN_ATTRIBUTES.contains(&&*self.name) {
+            return None;
+        }
+        let mut segments = self.value.children();
+        match (segments.next(), segments.next()) {
+            (Some(Segment::Expr(test)), None) => Some(test),
+            _ => None,
+        }
+    }
+
+    fn parse(
+        name: &ExpandedName,
+        value: &kuchiki::Attribute,
+        inline: bool,
+        span: Span,
+    ) -> Result<Self, Error> {
         let name: String = name.local.to_string();
-        let value = parse_inline(&value.value)?;
+        let value = if inline {
+            parse_inline(&value.value, span)?
+        } else {
+            Substitutable::literal(value.value.clone())
+        };
 
         Ok(Attribute { name, value })
     }
@@ -261,21 +759,66 @@ impl quote::ToTokens for Attribute {
     fn to_tokens(&self, tokens: &mut TokenStream2) {
         let key_name: String = self.name.to_string();
 
-        let str_iter_q = self
-            .value
-            .children()
-            .map(|segment| match segment {
-                Segment::Literal(cdata) => quote!(#cdata),
-                Segment::Expr(expr) => quote!(#expr.to_string()),
-            }).fold(
-                quote!(::std::iter::empty::<::std::borrow::Cow<str>>()),
-                |prefix, it| quote!(#prefix.chain(::std::iter::once(::std::borrow::Cow::from(#it)))),
-            );
+        // Which attributes are URLs or event handlers is left to `weft`, so
+        // that there is only one list of each. Event handlers are found at
+        // compile time, so that interpolating anything but a `JsString` is an
+        // error.
+        let value = self.value.children().fold(
+            quote!(::weft::AttrValue::new(#key_name)),
+            |value, segment| match segment {
+                Segment::Literal(cdata) => quote!(#value.literal(#cdata)),
+                Segment::Expr(expr) => quote!(
+                    ::weft::ToAttrValue::<{ ::weft::is_event_handler_attribute(#key_name) }>::append_to(
+                        &(#expr),
+                        #value,
+                    )
+                ),
+            },
+        );
+
+        tokens.append_all(quote!(::weft::AttrPair::new(
+            ::weft::QName::from(#key_name),
+            #value.finish().into()
+        )))
+    }
+}
 
-        let value = quote!(#str_iter_q.collect::<String>());
-        tokens.append_all(
-            quote!(::weft::AttrPair::new(::weft::QName::from(#key_name), #value.into())),
-        )
+impl quote::ToTokens for CaseDecl {
+    fn to_tokens(&self, tokens: &mut TokenStream2) {
+        self.pattern.to_tokens(tokens);
+        if let Some((if_, guard)) = &self.guard {
+            if_.to_tokens(tokens);
+            guard.to_tokens(tokens);
+        }
+    }
+}
+
+impl syn::parse::Parse for CaseDecl {
+    fn parse(buf: &syn::parse::ParseBuffer<'_>) -> Result<Self, syn::parse::Error> {
+        let pattern = Pat::parse_multi_with_leading_vert(buf)?;
+        let guard = if buf.peek(Token![if]) {
+            Some((buf.parse()?, buf.parse()?))
+        } else {
+            None
+        };
+        Ok(CaseDecl { pattern, guard })
+    }
+}
+
+impl quote::ToTokens for LetDecl {
+    fn to_tokens(&self, tokens: &mut TokenStream2) {
+        self.pattern.to_tokens(tokens);
+        self.eq.to_tokens(tokens);
+        self.expr.to_tokens(tokens);
+    }
+}
+
+impl syn::parse::Parse for LetDecl {
+    fn parse(buf: &syn::parse::ParseBuffer<'_>) -> Result<Self, syn::parse::Error> {
+        let pattern = Pat::parse_single(buf)?;
+        let eq = buf.parse()?;
+        let expr = buf.parse()?;
+        Ok(LetDecl { pattern, eq, expr })
     }
 }
 
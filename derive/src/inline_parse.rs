@@ -1,5 +1,8 @@
-use anyhow::Error;
+use crate::locations::{parse_at, AtOffset};
+use anyhow::{Context, Error};
 use log::*;
+use proc_macro2::Span;
+use syn::parse::Parse;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Segment {
@@ -13,40 +16,61 @@ pub struct Substitutable {
 }
 
 impl Substitutable {
+    pub fn literal(text: String) -> Self {
+        let children = if text.is_empty() {
+            vec![]
+        } else {
+            vec![Segment::Literal(text)]
+        };
+        Substitutable { children }
+    }
+
     pub fn children(&self) -> impl Iterator<Item = Segment> + '_ {
         self.children.iter().cloned()
     }
 }
 
-pub fn parse_inline(input: &str) -> Result<Substitutable, Error> {
-    let re = regex::Regex::new(r"\{\{([^}]|}[^}])*\}\}")?;
+/// Splits `input` into literal text and `{{ … }}` expressions. A literal
+/// `{{` may be written as `\{{`. Expressions are spanned at `span`; errors
+/// in them give their offset within `input`.
+pub fn parse_inline(input: &str, span: Span) -> Result<Substitutable, Error> {
+    let re = regex::Regex::new(r"\\\{\{|\{\{([^}]|}[^}])*\}\}")?;
     trace!("Scanning: {:?}", input);
     let mut last_match = 0;
+    let mut literal = String::new();
     let mut children = Vec::new();
     for it in re.find_iter(input) {
         trace!("Got: {:?}", it);
 
-        let previous = &input[last_match..it.start()];
-        if !previous.is_empty() {
-            children.push(Segment::Literal(previous.into()));
-        }
+        literal.push_str(&input[last_match..it.start()]);
+        last_match = it.end();
 
         let m = it.as_str();
+        if m == ESCAPED_OPEN {
+            literal.push_str("{{");
+            continue;
+        }
+
+        if !literal.is_empty() {
+            children.push(Segment::Literal(std::mem::take(&mut literal)));
+        }
+
         let range = 2..(m.len() - 2);
-        let expr: syn::Expr = syn::parse_str(&m[range])?;
+        let expr =
+            parse_at(syn::Expr::parse, &m[range], span).with_context(|| AtOffset(it.start()))?;
 
         children.push(Segment::Expr(expr));
-
-        last_match = it.end();
     }
-    let remainder = &input[last_match..];
-    if !remainder.is_empty() {
-        children.push(Segment::Literal(remainder.into()));
+    literal.push_str(&input[last_match..]);
+    if !literal.is_empty() {
+        children.push(Segment::Literal(literal));
     }
 
     Ok(Substitutable { children })
 }
 
+const ESCAPED_OPEN: &str = "\\{{";
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,13 +78,13 @@ mod tests {
 
     #[test]
     fn test_trivial_expr() {
-        let segments = parse_inline("{{ foo }}").expect("parse_inline");
+        let segments = parse_inline("{{ foo }}", Span::call_site()).expect("parse_inline");
         assert_eq!(&segments.children, &[Segment::Expr(parse_quote!(foo))])
     }
 
     #[test]
     fn test_trivial_literal() {
-        let segments = parse_inline("Hi!").expect("parse_inline");
+        let segments = parse_inline("Hi!", Span::call_site()).expect("parse_inline");
         let expected = vec![Segment::Literal("Hi!".into())];
         println!("Expected: {:?}", expected);
         println!("Got: {:?}", segments);
@@ -69,7 +93,7 @@ mod tests {
 
     #[test]
     fn test_mixed_1() {
-        let segments = parse_inline("A {{ foo }}").expect("parse_inline");
+        let segments = parse_inline("A {{ foo }}", Span::call_site()).expect("parse_inline");
         assert_eq!(
             &segments.children,
             &[
@@ -79,9 +103,23 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_escaped_open() {
+        let segments =
+            parse_inline("A \\{{ foo }} and {{ bar }}", Span::call_site()).expect("parse_inline");
+        assert_eq!(
+            &segments.children,
+            &[
+                Segment::Literal("A {{ foo }} and ".into()),
+                Segment::Expr(parse_quote!(bar))
+            ]
+        )
+    }
+
     #[test]
     fn test_mixed_2() {
-        let segments = parse_inline("I {{ verb }} with {{ noun }}.").expect("parse_inline");
+        let segments =
+            parse_inline("I {{ verb }} with {{ noun }}.", Span::call_site()).expect("parse_inline");
         assert_eq!(
             &segments.children,
             &[
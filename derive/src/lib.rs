@@ -11,14 +11,22 @@ use syn::Token;
 
 mod derive_renderable;
 mod inline_parse;
+mod locations;
 use crate::derive_renderable::*;
+use crate::locations::SourceMap;
 
 use anyhow::{anyhow, bail, Context, Error};
 use html5ever::tendril::TendrilSink;
 use kuchiki::NodeRef;
 use proc_macro::TokenStream;
 use quote::ToTokens;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+const DEFAULT_SELECTOR: &str = ":root";
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 enum TemplateSource {
@@ -30,6 +38,24 @@ enum TemplateSource {
 struct TemplateDerivation {
     template_source: TemplateSource,
     selector: String,
+    nth: Option<usize>,
+    keep_comments: bool,
+    // The `path` or `source` literal, which rustc errors in expressions are reported against.
+    span: proc_macro2::Span,
+}
+
+/// A template file that has already been parsed. We keep these so that many
+/// structs may be derived from fragments of one file without re-parsing it.
+#[derive(Clone)]
+struct ParsedFile {
+    modified: SystemTime,
+    whole_document: bool,
+    root: NodeRef,
+    sources: Rc<SourceMap>,
+}
+
+thread_local! {
+    static PARSED_FILES: RefCell<HashMap<PathBuf, ParsedFile>> = RefCell::new(HashMap::new());
 }
 
 /// Derives a `WeftRenderable` instance from a given html template.
@@ -42,11 +68,34 @@ struct TemplateDerivation {
 ///
 /// ### Finding the template source.
 /// * `path`: The path of the template relative to the crate root.
-///           Must be present at compile time.
+///   Must be present at compile time.
 /// * `source`: The template source specified inline as a string.
 ///
 /// One of `path` or `source` must be specified.
-
+///
+/// ### Selecting content
+/// * `selector`: A CSS selector for a single element, whose children will be
+///   rendered. By default, a template that begins with a doctype or `<html>`
+///   tag is rendered as a whole document; otherwise, the template is treated
+///   as a fragment.
+/// * `def`: Selects the element marked with a matching `weft-def="Name"`
+///   attribute. Cannot be combined with `selector`.
+/// * `nth`: When the selector matches more than one element, the zero-based
+///   index of the one to use.
+///
+/// Template files are parsed once per compilation, so deriving many structs
+/// from fragments of a single file is cheap.
+///
+/// ### Output
+/// * `keep_comments`: When `true`, comments in the template source are
+///   written to the output. Defaults to `false`, which strips them.
+///
+/// ## Errors
+/// Problems found while deriving, such as a malformed directive or an
+/// expression that fails to parse, are reported with the template file, line
+/// and column. Errors that `rustc` finds in expressions afterwards, such as
+/// type errors, can only be pointed at the `path` or `source` literal; they do
+/// not say where in the template the expression came from.
 #[proc_macro_derive(WeftRenderable, attributes(template))]
 pub fn derive_template(input: TokenStream) -> TokenStream {
     // Theoretically `rustc` provides it's own logging, but we
@@ -54,10 +103,20 @@ pub fn derive_template(input: TokenStream) -> TokenStream {
     env_logger::Builder::from_env("WEFT_LOG")
         .try_init()
         .unwrap_or_default();
-    let ast: syn::DeriveInput = syn::parse(input).unwrap();
-    match make_template(ast) {
+    let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+    match make_template(ast.clone()) {
         Ok(toks) => toks.into(),
-        Err(err) => panic!("Error: {:?}", err),
+        Err(err) => template_error(&ast, err).to_compile_error().into(),
+    }
+}
+
+/// Reports `err` against the `#[template(…)]` attribute, or failing that,
+/// the name of the item being derived.
+fn template_error(item: &syn::DeriveInput, err: Error) -> syn::Error {
+    let message = format!("{:#}", err);
+    match item.attrs.iter().find(|a| a.path().is_ident("template")) {
+        Some(attr) => syn::Error::new_spanned(attr, message),
+        None => syn::Error::new_spanned(&item.ident, message),
     }
 }
 
@@ -65,36 +124,70 @@ fn make_template(item: syn::DeriveInput) -> Result<proc_macro2::TokenStream, Err
     info!("Deriving for {}", item.ident);
     trace!("{:#?}", item);
     let config = TemplateDerivation::from_derive(&item).context("find template")?;
-    let dom = config.load()?;
+    let (dom, sources) = config.load()?;
 
-    let impl_body = derive_impl(&config, dom, item)?;
+    let impl_body = derive_impl(&config, dom, &sources, item)?;
 
     Ok(impl_body.into_token_stream())
 }
 
-fn parse_path(path: &Path) -> Result<NodeRef, Error> {
+fn read_path(path: &Path) -> Result<String, Error> {
     info!("Using template from {:?}", path);
-    let parser = kuchiki::parse_html().from_utf8();
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("Reading template from path {:?}", &path))?;
+
+    Ok(source)
+}
 
-    let root = parser
-        .from_file(path)
-        .with_context(|| format!("Parsing template from path {:?}", &path))?;
+/// Parses the template at `path`, re-using an earlier parse of the same file
+/// if it has not been modified since.
+fn parse_path(path: &Path) -> Result<ParsedFile, Error> {
+    let modified = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .with_context(|| format!("Checking template at path {:?}", path))?;
+
+    let cached = PARSED_FILES.with(|files| files.borrow().get(path).cloned());
+    if let Some(parsed) = cached.filter(|p| p.modified == modified) {
+        debug!("Re-using parsed template from {:?}", path);
+        return Ok(parsed);
+    }
 
-    Ok(root)
+    let source = read_path(path)?;
+    let root = parse_source(&source);
+    let sources = SourceMap::new(path.display().to_string(), &source, &root);
+    let parsed = ParsedFile {
+        modified,
+        whole_document: is_whole_document(&source),
+        root,
+        sources: Rc::new(sources),
+    };
+    PARSED_FILES.with(|files| files.borrow_mut().insert(path.to_owned(), parsed.clone()));
+    Ok(parsed)
 }
 
 fn parse_source(source: &str) -> kuchiki::NodeRef {
-    info!("Using inline template");
     let parser = kuchiki::parse_html();
 
     parser.one(source)
 }
 
+/// Returns true when the source explicitly begins a whole document (ie: with
+/// a doctype or `<html>` tag), rather than being a fragment that the parser
+/// has implicitly wrapped with `html`, `head` and `body` elements.
+fn is_whole_document(source: &str) -> bool {
+    let re = regex::Regex::new(r"(?is)^\s*(<!--.*?-->\s*)*<(!doctype|html)[\s>]")
+        .expect("document prologue regex");
+    re.is_match(source)
+}
+
 #[derive(Clone, Debug)]
 enum TemplateArg {
-    Path(PathBuf),
-    Source(String),
+    Path(syn::LitStr),
+    Source(syn::LitStr),
     Selector(String),
+    Def(syn::Ident),
+    Nth(usize),
+    KeepComments(bool),
 }
 
 impl syn::parse::Parse for TemplateArg {
@@ -102,14 +195,21 @@ impl syn::parse::Parse for TemplateArg {
         let id: syn::Ident = buf.parse()?;
         let _eq: Token![=] = buf.parse()?;
         if id == "path" {
-            let path: syn::LitStr = buf.parse()?;
-            Ok(TemplateArg::Path(PathBuf::from(path.value())))
+            Ok(TemplateArg::Path(buf.parse()?))
         } else if id == "source" {
-            let source: syn::LitStr = buf.parse()?;
-            Ok(TemplateArg::Source(source.value()))
+            Ok(TemplateArg::Source(buf.parse()?))
         } else if id == "selector" {
             let selector: syn::LitStr = buf.parse()?;
             Ok(TemplateArg::Selector(selector.value()))
+        } else if id == "def" {
+            let def: syn::LitStr = buf.parse()?;
+            Ok(TemplateArg::Def(def.parse()?))
+        } else if id == "nth" {
+            let nth: syn::LitInt = buf.parse()?;
+            Ok(TemplateArg::Nth(nth.base10_parse()?))
+        } else if id == "keep_comments" {
+            let keep: syn::LitBool = buf.parse()?;
+            Ok(TemplateArg::KeepComments(keep.value))
         } else {
             Err(buf.error(format!("Unrecognised template parameter: {}", id)))
         }
@@ -139,61 +239,127 @@ impl TemplateDerivation {
         let mut path = None;
         let mut source = None;
         let mut template_selector = None;
+        let mut def = None;
+        let mut nth = None;
+        let mut keep_comments = false;
 
         for a in args {
             match a {
                 TemplateArg::Path(p) => path = Some(p),
                 TemplateArg::Source(s) => source = Some(s),
                 TemplateArg::Selector(s) => template_selector = Some(s),
+                TemplateArg::Def(d) => def = Some(d),
+                TemplateArg::Nth(n) => nth = Some(n),
+                TemplateArg::KeepComments(k) => keep_comments = k,
             }
         }
 
-        let template_source = match (path, source) {
+        let (template_source, span) = match (path, source) {
             (Some(path), None) => {
-                let path = root_dir().join(path);
-                TemplateSource::Path(path)
+                let full_path = root_dir().join(path.value());
+                (TemplateSource::Path(full_path), path.span())
             },
             (None, Some(source)) => {
-                TemplateSource::Source(source)
+                (TemplateSource::Source(source.value()), source.span())
             },
             _ => bail!("Exactly one of `source` or `path` attributes must be specfied in `#[template(...)]")
         };
 
-        let selector = template_selector.unwrap_or_else(|| ":root".to_string());
+        let selector = match (template_selector, def) {
+            (Some(selector), None) => selector,
+            (None, Some(def)) => format!("[weft-def=\"{}\"]", def),
+            (None, None) => DEFAULT_SELECTOR.to_string(),
+            (Some(_), Some(_)) => {
+                bail!("At most one of `selector` or `def` may be specified in `#[template(...)]")
+            }
+        };
 
         let res = TemplateDerivation {
             template_source,
             selector,
+            nth,
+            keep_comments,
+            span,
         };
 
         Ok(res)
     }
 
-    fn load(&self) -> Result<NodeRef, Error> {
-        let root = match &self.template_source {
-            TemplateSource::Path(ref path) => parse_path(path)?,
-            TemplateSource::Source(ref source) => parse_source(source),
+    /// Returns the nodes to render, along with the source map for errors.
+    fn load(&self) -> Result<(Vec<NodeRef>, Rc<SourceMap>), Error> {
+        let (whole_document, root, sources) = match &self.template_source {
+            TemplateSource::Path(ref path) => {
+                let parsed = parse_path(path)?;
+                (parsed.whole_document, parsed.root, parsed.sources)
+            }
+            TemplateSource::Source(ref source) => {
+                info!("Using inline template");
+                let root = parse_source(source);
+                let sources = SourceMap::new("inline template".into(), source, &root);
+                (is_whole_document(source), root, Rc::new(sources))
+            }
         };
 
-        let content = self
-            .find_root_from(root)
-            .ok_or_else(|| anyhow!("Could not locate root of parsed document?"))?;
-
-        Ok(content)
+        let content = self.select_content(whole_document, root)?;
+        Ok((content, sources))
     }
 
-    fn find_root_from(&self, node: NodeRef) -> Option<NodeRef> {
-        if let Ok(mut roots) = node.select(&self.selector) {
-            let first = roots.next()?;
-            if roots.next().is_some() {
-                warn!("Selector {} returns more than one match", self.selector);
-                return None;
+    fn select_content(&self, whole_document: bool, root: NodeRef) -> Result<Vec<NodeRef>, Error> {
+        if self.selector == DEFAULT_SELECTOR {
+            if whole_document {
+                return Ok(root.children().collect());
             }
 
-            return Some(first.as_node().clone());
+            // Only render the content of the implied `html`, `head` and `body`
+            // elements. Comments before any content are attached to the
+            // document itself, so we keep those too if asked.
+            let mut content = Vec::new();
+            for child in root.children() {
+                if child.as_comment().is_some() {
+                    if self.keep_comments {
+                        content.push(child);
+                    }
+                    continue;
+                }
+                content.extend(
+                    child
+                        .select("head, body")
+                        .map_err(|()| anyhow!("Could not parse selector"))?
+                        .flat_map(|elt| elt.as_node().children()),
+                );
+            }
+            return Ok(content);
         }
 
-        None
+        let content = self.find_root_from(root)?;
+
+        Ok(content.children().collect())
+    }
+
+    fn find_root_from(&self, node: NodeRef) -> Result<NodeRef, Error> {
+        let mut roots = node
+            .select(&self.selector)
+            .map_err(|()| anyhow!("Could not parse selector {:?}", self.selector))?;
+
+        let found = match self.nth {
+            Some(n) => roots.nth(n).ok_or_else(|| {
+                anyhow!("Selector {:?} has no match at index {}", self.selector, n)
+            })?,
+            None => {
+                let first = roots
+                    .next()
+                    .ok_or_else(|| anyhow!("Selector {:?} matches nothing", self.selector))?;
+                if roots.next().is_some() {
+                    bail!(
+                        "Selector {:?} matches more than one element; use `nth` to choose one",
+                        self.selector
+                    );
+                }
+                first
+            }
+        };
+
+        Ok(found.as_node().clone())
     }
 }
 
@@ -286,17 +452,162 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn cannot_parse_with_multiple_root_matches() {
         env_logger::try_init().unwrap_or_default();
 
-        let _ = derive_template(
-            quote!(
-                #[template(source = "<p>foo</p><p>bar</p>", selector = "p")]
-                struct MultipleRoots;
-            )
-            .into(),
+        let deriv = parse_quote!(
+            #[template(source = "<p>foo</p><p>bar</p>", selector = "p")]
+            struct MultipleRoots;
+        );
+
+        let err = make_template(deriv).expect_err("template should not derive");
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("nth"),
+            "Message {:?} should suggest `nth`",
+            message
+        );
+    }
+
+    #[test]
+    fn strips_comments_by_default() {
+        let deriv = parse_quote!(
+            #[template(source = "<p>Stuff</p>")]
+            struct X;
+        );
+
+        let conf = TemplateDerivation::from_derive(&deriv).expect("parse derive");
+
+        assert!(!conf.keep_comments);
+    }
+
+    #[test]
+    fn will_extract_keep_comments() {
+        let deriv = parse_quote!(
+            #[template(source = "<p>Stuff</p>", keep_comments = true)]
+            struct X;
         );
+
+        let conf = TemplateDerivation::from_derive(&deriv).expect("parse derive");
+
+        assert!(conf.keep_comments);
+    }
+
+    #[test]
+    fn reports_location_of_invalid_expression() {
+        let deriv: syn::DeriveInput = parse_quote!(
+            #[template(source = "<div>\n  <p weft-if=\"self.0 +\">One</p>\n</div>")]
+            struct X(bool);
+        );
+
+        let err = make_template(deriv.clone()).expect_err("template should not derive");
+        let message = template_error(&deriv, err).to_string();
+        assert!(
+            message.starts_with("inline template:2:6: In attribute weft-if:"),
+            "Unexpected message: {}",
+            message
+        );
+    }
+
+    #[test]
+    fn reports_location_of_invalid_inline_expression() {
+        let deriv: syn::DeriveInput = parse_quote!(
+            #[template(source = "<div>\n<p title='x {{ self.0 + }}'></p>\n</div>")]
+            struct X(bool);
+        );
+
+        let err = make_template(deriv).expect_err("template should not derive");
+        let message = format!("{:#}", err);
+        assert!(
+            message.starts_with("inline template:2:13: In attribute title:"),
+            "Unexpected message: {}",
+            message
+        );
+    }
+
+    #[test]
+    fn reports_location_of_misplaced_element() {
+        let deriv: syn::DeriveInput = parse_quote!(
+            #[template(source = "<ul>\n<li>One</li>\n<li weft-else>Two</li>\n</ul>")]
+            struct X;
+        );
+
+        let err = make_template(deriv).expect_err("template should not derive");
+        let message = format!("{:#}", err);
+        assert!(
+            message.starts_with("inline template:3:1: weft-else"),
+            "Unexpected message: {}",
+            message
+        );
+    }
+
+    #[test]
+    fn cannot_use_else_without_if() {
+        let deriv = parse_quote!(
+            #[template(source = "<p>One</p><p weft-else>Two</p>")]
+            struct X;
+        );
+
+        let res = make_template(deriv);
+        assert!(res.is_err(), "Template should not derive: {:?}", res)
+    }
+
+    #[test]
+    fn cannot_use_else_after_non_conditional_sibling() {
+        let deriv = parse_quote!(
+            #[template(source = "<p weft-if=\"true\">One</p>text<p weft-else>Two</p>")]
+            struct X;
+        );
+
+        let res = make_template(deriv);
+        assert!(res.is_err(), "Template should not derive: {:?}", res)
+    }
+
+    #[test]
+    fn cannot_use_case_outside_match() {
+        let deriv = parse_quote!(
+            #[template(source = "<p weft-case=\"_\">One</p>")]
+            struct X;
+        );
+
+        let res = make_template(deriv);
+        assert!(res.is_err(), "Template should not derive: {:?}", res)
+    }
+
+    #[test]
+    fn cannot_use_for_empty_without_for() {
+        let deriv = parse_quote!(
+            #[template(source = "<p>One</p><p weft-for-empty>None</p>")]
+            struct X;
+        );
+
+        let res = make_template(deriv);
+        assert!(res.is_err(), "Template should not derive: {:?}", res)
+    }
+
+    #[test]
+    fn will_extract_def_as_selector() {
+        let deriv = parse_quote!(
+            #[template(path = "hello.html", def = "Row", nth = 2)]
+            struct X;
+        );
+
+        let conf = TemplateDerivation::from_derive(&deriv).expect("parse derive");
+
+        assert_eq!(conf.selector, "[weft-def=\"Row\"]");
+        assert_eq!(conf.nth, Some(2));
+    }
+
+    #[test]
+    fn cannot_parse_with_both_selector_and_def() {
+        let deriv = quote!(
+            #[template(path = "hello.html", selector = "p", def = "Row")]
+            struct X;
+        );
+
+        let parsed = syn::parse2(deriv.clone()).expect("parse");
+        let res = TemplateDerivation::from_derive(&parsed);
+        assert!(res.is_err(), "Template {} should not parse", deriv)
     }
 
     #[test]
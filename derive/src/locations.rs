@@ -0,0 +1,363 @@
+//! Finds where elements and attributes appear in a template's source, so that
+//! errors can point at them. The HTML parser does not record positions, so we
+//! scan the source for each element's start tag, in document order.
+use kuchiki::{Node, NodeRef};
+use proc_macro2::{Group, Span, TokenStream as TokenStream2, TokenTree};
+use std::collections::HashMap;
+use std::fmt;
+use syn::parse::Parser;
+
+/// A position within a template source, for use in error messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Location {
+    name: String,
+    line: usize,
+    column: usize,
+}
+
+/// Marks an error as arising from the named attribute of an element.
+#[derive(Debug)]
+pub(crate) struct InAttribute(pub(crate) String);
+
+/// Marks an error as arising at a byte offset within an attribute value or
+/// text node, such as the start of a `{{ … }}` expression.
+#[derive(Debug)]
+pub(crate) struct AtOffset(pub(crate) usize);
+
+#[derive(Debug, Default)]
+struct ElementOffsets {
+    start: usize,
+    // Just after the end of the start tag.
+    content: usize,
+    attributes: HashMap<String, AttributeOffsets>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AttributeOffsets {
+    name: usize,
+    value: Option<usize>,
+}
+
+#[derive(Debug)]
+pub(crate) struct SourceMap {
+    name: String,
+    source: String,
+    elements: HashMap<*const Node, ElementOffsets>,
+}
+
+impl SourceMap {
+    /// Indexes the elements of `root`, which was parsed from `source`. Those
+    /// implied by the parser, such as a missing `<body>`, have no location.
+    pub(crate) fn new(name: String, source: &str, root: &NodeRef) -> Self {
+        let mut elements = HashMap::new();
+        let mut pos = 0;
+        for node in root.inclusive_descendants() {
+            let data = match node.as_element() {
+                Some(data) => data,
+                None => continue,
+            };
+            if let Some(offsets) = scan_start_tag(source, pos, &data.name.local) {
+                pos = match TEXT_ELEMENTS.iter().find(|t| **t == &*data.name.local) {
+                    // Anything that looks like a tag in here is only text.
+                    Some(name) => skip_to_end_tag(source, offsets.content, name),
+                    None => offsets.start + 1,
+                };
+                elements.insert(&*node as *const Node, offsets);
+            }
+        }
+
+        SourceMap {
+            name,
+            source: source.to_string(),
+            elements,
+        }
+    }
+
+    /// Finds the location of the named attribute of `node`, or of the nearest
+    /// element enclosing `node` if that is unknown. Where `within` is given,
+    /// it is an offset into the attribute's value, or into the text of `node`.
+    pub(crate) fn locate(
+        &self,
+        node: &NodeRef,
+        attribute: Option<&str>,
+        within: Option<usize>,
+    ) -> Option<Location> {
+        if node.as_text().is_some() && node.previous_sibling().is_none() {
+            let parent = node.parent()?;
+            if let Some(offsets) = self.offsets(&parent) {
+                return Some(self.location(offsets.content + within.unwrap_or(0)));
+            }
+        }
+
+        let offsets = node.inclusive_ancestors().find_map(|n| self.offsets(&n))?;
+        let attribute = attribute.and_then(|name| offsets.attributes.get(name));
+        let offset = match (attribute, within) {
+            (
+                Some(AttributeOffsets {
+                    value: Some(value), ..
+                }),
+                Some(within),
+            ) => value + within,
+            (Some(attribute), _) => attribute.name,
+            (None, _) => offsets.start,
+        };
+        Some(self.location(offset))
+    }
+
+    fn offsets(&self, node: &NodeRef) -> Option<&ElementOffsets> {
+        self.elements.get(&(&**node as *const Node))
+    }
+
+    fn location(&self, offset: usize) -> Location {
+        let before = &self.source[..offset.min(self.source.len())];
+        let line_start = before.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        Location {
+            name: self.name.clone(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}:{}:{}", self.name, self.line, self.column)
+    }
+}
+
+impl fmt::Display for InAttribute {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "In attribute {}", self.0)
+    }
+}
+
+impl fmt::Display for AtOffset {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "In expression")
+    }
+}
+
+/// Parses Rust syntax from template text, such that rustc reports any errors
+/// in it (eg: type errors) against `span`, rather than the derive as a whole.
+pub(crate) fn parse_at<P: Parser>(parser: P, text: &str, span: Span) -> syn::Result<P::Output> {
+    let tokens: TokenStream2 = syn::parse_str(text)?;
+    parser.parse2(respan(tokens, Span::call_site().located_at(span)))
+}
+
+/// As [`parse_at`], for any type that implements [`syn::parse::Parse`].
+pub(crate) fn parse_str_at<T: syn::parse::Parse>(text: &str, span: Span) -> syn::Result<T> {
+    parse_at(T::parse, text, span)
+}
+
+fn respan(tokens: TokenStream2, span: Span) -> TokenStream2 {
+    tokens
+        .into_iter()
+        .map(|mut tree| {
+            if let TokenTree::Group(group) = &tree {
+                let mut respanned = Group::new(group.delimiter(), respan(group.stream(), span));
+                respanned.set_span(span);
+                tree = TokenTree::Group(respanned);
+            } else {
+                tree.set_span(span);
+            }
+            tree
+        })
+        .collect()
+}
+
+/// Elements whose content the parser reads as text, up to the matching end tag.
+const TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+/// Returns the offset of the end tag for `name` at or after `from`, or the
+/// end of `source` if there is none.
+fn skip_to_end_tag(source: &str, from: usize, name: &str) -> usize {
+    let end_tag = format!("</{}", name);
+    source.as_bytes()[from..]
+        .windows(end_tag.len())
+        .position(|w| w.eq_ignore_ascii_case(end_tag.as_bytes()))
+        .map_or(source.len(), |idx| from + idx)
+}
+
+/// Finds the next start tag for the element `name` at or after `from`,
+/// skipping over comments.
+fn scan_start_tag(source: &str, from: usize, name: &str) -> Option<ElementOffsets> {
+    let bytes = source.as_bytes();
+    let mut pos = from;
+    while let Some(idx) = source[pos..].find('<') {
+        let start = pos + idx;
+        let rest = &source[start + 1..];
+        if rest.starts_with("!--") {
+            pos = rest.find("-->").map(|end| start + 1 + end + 3)?;
+            continue;
+        }
+        let after = start + 1 + name.len();
+        let name_matches = rest
+            .get(..name.len())
+            .is_some_and(|tag| tag.eq_ignore_ascii_case(name));
+        let name_ends = bytes
+            .get(after)
+            .is_none_or(|b| b.is_ascii_whitespace() || *b == b'>' || *b == b'/');
+        if name_matches && name_ends {
+            let (attributes, end) = scan_attributes(source, after);
+            return Some(ElementOffsets {
+                start,
+                content: end + 1,
+                attributes,
+            });
+        }
+        pos = start + 1;
+    }
+    None
+}
+
+/// Records the offsets of each attribute in a start tag, along with the
+/// offset of the tag's closing `>`.
+fn scan_attributes(source: &str, from: usize) -> (HashMap<String, AttributeOffsets>, usize) {
+    let bytes = source.as_bytes();
+    let mut attributes = HashMap::new();
+    let mut pos = from;
+    loop {
+        while bytes
+            .get(pos)
+            .is_some_and(|b| b.is_ascii_whitespace() || *b == b'/')
+        {
+            pos += 1;
+        }
+        match bytes.get(pos) {
+            None | Some(b'>') => return (attributes, pos),
+            Some(_) => {}
+        }
+
+        let name_start = pos;
+        while bytes
+            .get(pos)
+            .is_some_and(|b| !b.is_ascii_whitespace() && !b"=>/".contains(b))
+        {
+            pos += 1;
+        }
+        if pos == name_start {
+            // A stray `=`; skip it rather than looping forever.
+            pos += 1;
+            continue;
+        }
+        let name = source[name_start..pos].to_ascii_lowercase();
+        let attribute = attributes.entry(name).or_insert(AttributeOffsets {
+            name: name_start,
+            value: None,
+        });
+
+        while bytes.get(pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        if bytes.get(pos) != Some(&b'=') {
+            continue;
+        }
+        pos += 1;
+        while bytes.get(pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        match bytes.get(pos) {
+            Some(&quote) if quote == b'"' || quote == b'\'' => {
+                pos += 1;
+                attribute.value.get_or_insert(pos);
+                while bytes.get(pos).is_some_and(|b| *b != quote) {
+                    pos += 1;
+                }
+                pos += 1;
+            }
+            _ => {
+                attribute.value.get_or_insert(pos);
+                while bytes
+                    .get(pos)
+                    .is_some_and(|b| !b.is_ascii_whitespace() && *b != b'>')
+                {
+                    pos += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use html5ever::tendril::TendrilSink;
+
+    fn map_of(source: &str) -> (NodeRef, SourceMap) {
+        let root = kuchiki::parse_html().one(source);
+        let map = SourceMap::new("test.html".into(), source, &root);
+        (root, map)
+    }
+
+    fn position(map: &SourceMap, node: &NodeRef, attr: Option<&str>) -> (usize, usize) {
+        let loc = map.locate(node, attr, None).expect("location");
+        (loc.line, loc.column)
+    }
+
+    #[test]
+    fn should_locate_elements() {
+        let (root, map) = map_of("<div>\n  <p>One</p>\n  <p>Two</p>\n</div>");
+        let ps = root.select("p").expect("select").collect::<Vec<_>>();
+
+        assert_eq!(position(&map, ps[0].as_node(), None), (2, 3));
+        assert_eq!(position(&map, ps[1].as_node(), None), (3, 3));
+    }
+
+    #[test]
+    fn should_skip_tags_inside_script_and_style() {
+        let (root, map) = map_of(
+            "<div><script>if (a <p && c) {}</script>\n  <p>One</p>\n  <style>b<b {}</style><b>Two</b>\n</div>",
+        );
+        let p = root.select_first("p").expect("select");
+        let b = root.select_first("b").expect("select");
+
+        assert_eq!(position(&map, p.as_node(), None), (2, 3));
+        assert_eq!(position(&map, b.as_node(), None), (3, 24));
+    }
+
+    #[test]
+    fn should_locate_attributes() {
+        let (root, map) = map_of("<div>\n  <p class='a>b' weft-if=\"x\">One</p>\n</div>");
+        let p = root.select_first("p").expect("select");
+
+        assert_eq!(position(&map, p.as_node(), Some("weft-if")), (2, 18));
+    }
+
+    #[test]
+    fn should_locate_within_attribute_values() {
+        let (root, map) = map_of("<div>\n  <p title=\"A {{ x }}\">One</p>\n</div>");
+        let p = root.select_first("p").expect("select");
+        let loc = map
+            .locate(p.as_node(), Some("title"), Some(2))
+            .expect("loc");
+
+        assert_eq!((loc.line, loc.column), (2, 15));
+    }
+
+    #[test]
+    fn should_locate_leading_text() {
+        let (root, map) = map_of("<div>\n<span>A {{ x }}</span></div>");
+        let span = root.select_first("span").expect("select");
+        let text = span.as_node().first_child().expect("text");
+        let loc = map.locate(&text, None, Some(2)).expect("loc");
+
+        assert_eq!((loc.line, loc.column), (2, 9));
+    }
+
+    #[test]
+    fn should_locate_later_text_by_enclosing_element() {
+        let (root, map) = map_of("<div>\n<span><b>A</b>{{ x }}</span></div>");
+        let span = root.select_first("span").expect("select");
+        let text = span.as_node().last_child().expect("text");
+
+        assert_eq!(position(&map, &text, None), (2, 1));
+    }
+
+    #[test]
+    fn should_skip_tags_within_comments() {
+        let (root, map) = map_of("<!-- <p> -->\n<p>One</p>");
+        let p = root.select_first("p").expect("select");
+
+        assert_eq!(position(&map, p.as_node(), None), (2, 1));
+    }
+}
@@ -1,6 +1,4 @@
-use std::io;
-
-use weft::{AttrPair, QName, RenderTarget, WeftRenderable};
+use weft::{AttrPair, Error, QName, RenderTarget, WeftRenderable};
 
 struct ErasedRenderTarget<'a>(&'a mut dyn RenderTarget);
 
@@ -8,46 +6,61 @@ struct ErasedRenderTarget<'a>(&'a mut dyn RenderTarget);
 /// we need a trait object. Eg: for a `Vec<Box<dyn ErasedRenderable>>`.
 pub trait ErasedRenderable {
     /// Outputs a representation of this object to the target.
-    fn erased_render_to(&self, target: &mut dyn RenderTarget) -> Result<(), io::Error>;
+    fn erased_render_to(&self, target: &mut dyn RenderTarget) -> Result<(), Error>;
 }
 
 impl<T> ErasedRenderable for T
 where
     T: WeftRenderable,
 {
-    fn erased_render_to(&self, target: &mut dyn RenderTarget) -> Result<(), io::Error> {
+    fn erased_render_to(&self, target: &mut dyn RenderTarget) -> Result<(), Error> {
         self.render_to(&mut ErasedRenderTarget(target))
     }
 }
 
 impl WeftRenderable for dyn ErasedRenderable {
-    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
         self.erased_render_to(target)
     }
 }
 
 impl<'a> RenderTarget for ErasedRenderTarget<'a> {
-    fn start_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), io::Error> {
+    fn start_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), Error> {
         self.0.start_element_attrs(name, attrs)
     }
-    fn text(&mut self, content: &str) -> Result<(), io::Error> {
+    fn text(&mut self, content: &str) -> Result<(), Error> {
         self.0.text(content)
     }
-    fn end_element(&mut self, name: QName) -> Result<(), io::Error> {
+    fn raw_html(&mut self, html: &str) -> Result<(), Error> {
+        self.0.raw_html(html)
+    }
+    fn raw_text(&mut self, content: &str) -> Result<(), Error> {
+        self.0.raw_text(content)
+    }
+    fn end_element(&mut self, name: QName) -> Result<(), Error> {
         self.0.end_element(name)
     }
+    fn comment(&mut self, content: &str) -> Result<(), Error> {
+        self.0.comment(content)
+    }
+    fn doctype(&mut self) -> Result<(), Error> {
+        self.0.doctype()
+    }
+    fn void_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), Error> {
+        self.0.void_element_attrs(name, attrs)
+    }
 }
 
 /// Renderer created from an anonymous function
 pub struct FnRenderer<F>(F);
 
 /// Allows easily creating a renderer from an anonymous function.
-pub fn render_fn<F: Fn(&mut dyn RenderTarget) -> Result<(), io::Error>>(f: F) -> FnRenderer<F> {
+pub fn render_fn<F: Fn(&mut dyn RenderTarget) -> Result<(), Error>>(f: F) -> FnRenderer<F> {
     FnRenderer(f)
 }
 
-impl<F: Fn(&mut dyn RenderTarget) -> Result<(), io::Error>> WeftRenderable for FnRenderer<F> {
-    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
+impl<F: Fn(&mut dyn RenderTarget) -> Result<(), Error>> WeftRenderable for FnRenderer<F> {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
         (self.0)(target)
     }
 }
@@ -48,3 +48,21 @@ fn should_support_fn_content() {
         expected
     )
 }
+
+#[test]
+fn should_forward_pre_escaped_content() {
+    let view = WithBoxedContent {
+        child: Box::new(weft::PreEscaped::dangerously_trust("<b>Bold</b>")),
+    };
+
+    let s = weft::render_to_string(view).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<p><b>Bold</b></p>";
+    assert!(
+        s.contains(expected),
+        "String {:?} contains {:?}",
+        s,
+        expected
+    )
+}
@@ -0,0 +1,164 @@
+//! The error type returned when rendering fails.
+use std::{error::Error as StdError, fmt, io};
+
+/// Broadly, why rendering failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Writing the output failed.
+    Io,
+    /// A template or renderer could not produce its output, eg: because of
+    /// missing data, or markup that cannot be represented.
+    Render,
+}
+
+/// An error from rendering a template, wrapping the underlying cause.
+///
+/// Where known, this also records which templates were being rendered, and
+/// the path of the element that was open when the error occurred.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    source: Box<dyn StdError + Send + Sync + 'static>,
+    templates: Vec<&'static str>,
+    element_path: Option<String>,
+}
+
+impl Error {
+    /// Creates an error for a failure to render, caused by `source`. This may
+    /// be any error type, or a message string.
+    pub fn render(source: impl Into<Box<dyn StdError + Send + Sync + 'static>>) -> Self {
+        Error::new(ErrorKind::Render, source.into())
+    }
+
+    fn new(kind: ErrorKind, source: Box<dyn StdError + Send + Sync + 'static>) -> Self {
+        Error {
+            kind,
+            source,
+            templates: Vec::new(),
+            element_path: None,
+        }
+    }
+
+    /// Records that the error occurred whilst rendering the template named
+    /// `name`. This is called by derived implementations.
+    pub fn in_template(mut self, name: &'static str) -> Self {
+        self.templates.push(name);
+        self
+    }
+
+    /// Records the path of the element that was open when the error
+    /// occurred, unless one is already known.
+    pub(crate) fn at_element_path(mut self, path: impl FnOnce() -> String) -> Self {
+        if self.element_path.is_none() {
+            self.element_path = Some(path()).filter(|p| !p.is_empty());
+        }
+        self
+    }
+
+    /// Returns why rendering failed.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns true if writing the output failed.
+    pub fn is_io(&self) -> bool {
+        self.kind == ErrorKind::Io
+    }
+
+    /// Returns the names of the templates being rendered when the error
+    /// occurred, innermost first.
+    pub fn templates(&self) -> &[&'static str] {
+        &self.templates
+    }
+
+    /// Returns the path of the element that was open when the error
+    /// occurred, such as `html > body > ul > li[3]`. An index is given where
+    /// an element is not the first of that name amongst its siblings.
+    pub fn element_path(&self) -> Option<&str> {
+        self.element_path.as_deref()
+    }
+
+    /// Returns the underlying error if it is of type `E`.
+    pub fn downcast_ref<E: StdError + 'static>(&self) -> Option<&E> {
+        self.source.downcast_ref()
+    }
+
+    /// Returns the underlying error if it is of type `E`, or otherwise this
+    /// error unchanged.
+    pub fn downcast<E: StdError + 'static>(self) -> Result<E, Self> {
+        let Error {
+            kind,
+            source,
+            templates,
+            element_path,
+        } = self;
+        match source.downcast() {
+            Ok(err) => Ok(*err),
+            Err(source) => Err(Error {
+                kind,
+                source,
+                templates,
+                element_path,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ErrorKind::Io => write!(f, "Writing output failed: {}", self.source)?,
+            ErrorKind::Render => write!(f, "Rendering failed: {}", self.source)?,
+        }
+        if !self.templates.is_empty() {
+            write!(f, " in {}", self.templates.join(" within "))?;
+        }
+        if let Some(path) = &self.element_path {
+            write!(f, " at {}", path)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+/// An error raised with `?` within a template expression. Any error type
+/// converts into this, and it converts into an [`Error`] of kind
+/// [`ErrorKind::Render`], unless it already was an [`Error`].
+///
+/// This does not itself implement [`std::error::Error`], as that would
+/// prevent the blanket conversion.
+#[derive(Debug)]
+pub struct ExprError(Box<dyn StdError + Send + Sync + 'static>);
+
+impl<E: StdError + Send + Sync + 'static> From<E> for ExprError {
+    fn from(err: E) -> Self {
+        ExprError(Box::new(err))
+    }
+}
+
+impl From<ExprError> for Error {
+    fn from(err: ExprError) -> Self {
+        match err.0.downcast::<Error>() {
+            Ok(err) => *err,
+            Err(source) => Error::new(ErrorKind::Render, source),
+        }
+    }
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::new(ErrorKind::Io, Box::new(err))
+    }
+}
@@ -1,6 +1,8 @@
 //! Extensions and helpers for use within templates and renderers.
-use crate::template::{RenderTarget, WeftRenderable};
-use std::{fmt, io};
+use crate::error::Error;
+use crate::template::{write_js_string_escaped, RenderTarget, WeftRenderable};
+use crate::urls::{AttrValue, ToUrlAttr};
+use std::{borrow::Cow, fmt, fmt::Write, rc::Rc, sync::Arc};
 
 /// A helper struct for the `Displayable` trait.
 pub struct Displayer<'a, D>(&'a D);
@@ -11,32 +13,439 @@ pub trait Displayable: Sized {
     fn display(&self) -> Displayer<'_, Self>;
 }
 
+/// Markup that has already been escaped or sanitized, and so will be written
+/// to the output verbatim.
+///
+/// This can only be created via [`PreEscaped::dangerously_trust`], so that
+/// uses are easy to find when reviewing code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreEscaped<S>(S);
+
+impl<S: AsRef<str>> PreEscaped<S> {
+    /// Marks `html` as safe to write without escaping. The caller must ensure
+    /// that it is well formed, and does not contain untrusted markup.
+    pub fn dangerously_trust(html: S) -> Self {
+        PreEscaped(html)
+    }
+}
+
+/// A value to be interpolated into an event handler attribute, such as
+/// `onclick`, as a quoted Javascript string literal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsString<D>(D);
+
+impl<D: fmt::Display> JsString<D> {
+    /// Wraps `value` so that it may be used within an event handler attribute.
+    pub fn new(value: D) -> Self {
+        JsString(value)
+    }
+}
+
+/// Converts a value for interpolation into an event handler attribute. This
+/// is only implemented for [`JsString`], so that interpolating anything else
+/// is a compile time error.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be interpolated into an event handler attribute",
+    label = "wrap this in `JsString::new(…)` to encode it as a Javascript string"
+)]
+pub trait ToEventHandlerAttr {
+    /// Returns the value encoded as a Javascript string literal.
+    fn to_event_handler_attr(&self) -> String;
+}
+
+impl<D: fmt::Display> ToEventHandlerAttr for JsString<D> {
+    fn to_event_handler_attr(&self) -> String {
+        let mut out = b"\"".to_vec();
+        write_js_string_escaped(&mut out, &self.0.to_string())
+            .expect("writing to a Vec cannot fail");
+        out.push(b'"');
+        String::from_utf8(out).expect("escaped string is valid UTF-8")
+    }
+}
+
+/// The event handler attributes defined by HTML, and common extensions to it.
+const EVENT_HANDLER_ATTRIBUTES: &[&str] = &[
+    "onabort",
+    "onafterprint",
+    "onanimationcancel",
+    "onanimationend",
+    "onanimationiteration",
+    "onanimationstart",
+    "onauxclick",
+    "onbeforecopy",
+    "onbeforecut",
+    "onbeforeinput",
+    "onbeforematch",
+    "onbeforepaste",
+    "onbeforeprint",
+    "onbeforetoggle",
+    "onbeforeunload",
+    "onblur",
+    "oncancel",
+    "oncanplay",
+    "oncanplaythrough",
+    "onchange",
+    "onclick",
+    "onclose",
+    "oncommand",
+    "oncontentvisibilityautostatechange",
+    "oncontextlost",
+    "oncontextmenu",
+    "oncontextrestored",
+    "oncopy",
+    "oncuechange",
+    "oncut",
+    "ondblclick",
+    "ondrag",
+    "ondragend",
+    "ondragenter",
+    "ondragexit",
+    "ondragleave",
+    "ondragover",
+    "ondragstart",
+    "ondrop",
+    "ondurationchange",
+    "onemptied",
+    "onended",
+    "onerror",
+    "onfocus",
+    "onfocusin",
+    "onfocusout",
+    "onformdata",
+    "onfullscreenchange",
+    "onfullscreenerror",
+    "ongotpointercapture",
+    "onhashchange",
+    "oninput",
+    "oninvalid",
+    "onkeydown",
+    "onkeypress",
+    "onkeyup",
+    "onlanguagechange",
+    "onload",
+    "onloadeddata",
+    "onloadedmetadata",
+    "onloadend",
+    "onloadstart",
+    "onlostpointercapture",
+    "onmessage",
+    "onmessageerror",
+    "onmousedown",
+    "onmouseenter",
+    "onmouseleave",
+    "onmousemove",
+    "onmouseout",
+    "onmouseover",
+    "onmouseup",
+    "onmousewheel",
+    "onoffline",
+    "ononline",
+    "onpagehide",
+    "onpagereveal",
+    "onpageshow",
+    "onpageswap",
+    "onpaste",
+    "onpause",
+    "onplay",
+    "onplaying",
+    "onpointercancel",
+    "onpointerdown",
+    "onpointerenter",
+    "onpointerleave",
+    "onpointermove",
+    "onpointerout",
+    "onpointerover",
+    "onpointerrawupdate",
+    "onpointerup",
+    "onpopstate",
+    "onprogress",
+    "onratechange",
+    "onreadystatechange",
+    "onrejectionhandled",
+    "onreset",
+    "onresize",
+    "onscroll",
+    "onscrollend",
+    "onsearch",
+    "onsecuritypolicyviolation",
+    "onseeked",
+    "onseeking",
+    "onselect",
+    "onselectionchange",
+    "onselectstart",
+    "onshow",
+    "onslotchange",
+    "onstalled",
+    "onstorage",
+    "onsubmit",
+    "onsuspend",
+    "ontimeupdate",
+    "ontoggle",
+    "ontouchcancel",
+    "ontouchend",
+    "ontouchmove",
+    "ontouchstart",
+    "ontransitioncancel",
+    "ontransitionend",
+    "ontransitionrun",
+    "ontransitionstart",
+    "onunhandledrejection",
+    "onunload",
+    "onvisibilitychange",
+    "onvolumechange",
+    "onwaiting",
+    "onwebkitanimationend",
+    "onwebkitanimationiteration",
+    "onwebkitanimationstart",
+    "onwebkittransitionend",
+    "onwheel",
+];
+
+/// Returns true if `name` is an event handler attribute, such as `onclick`,
+/// whose value is run as Javascript. This is a `const fn` so that derived
+/// templates can use it to choose how to encode a value at compile time.
+pub const fn is_event_handler_attribute(name: &str) -> bool {
+    let mut i = 0;
+    while i < EVENT_HANDLER_ATTRIBUTES.len() {
+        if EVENT_HANDLER_ATTRIBUTES[i]
+            .as_bytes()
+            .eq_ignore_ascii_case(name.as_bytes())
+        {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Appends a value interpolated into an attribute to an [`AttrValue`]. The
+/// parameter `EVENT_HANDLER` is whether the attribute is an event handler,
+/// as given by [`is_event_handler_attribute`], which requires [`JsString`].
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be interpolated into this attribute",
+    note = "event handler attributes, such as `onclick`, require `JsString::new(…)`"
+)]
+pub trait ToAttrValue<const EVENT_HANDLER: bool> {
+    /// Appends the encoded value to `value`.
+    fn append_to(&self, value: AttrValue) -> AttrValue;
+}
+
+impl<T: ToEventHandlerAttr + ?Sized> ToAttrValue<true> for T {
+    fn append_to(&self, value: AttrValue) -> AttrValue {
+        value.literal(&self.to_event_handler_attr())
+    }
+}
+
+impl<T: ToUrlAttr + ?Sized> ToAttrValue<false> for T {
+    fn append_to(&self, value: AttrValue) -> AttrValue {
+        value.value(self)
+    }
+}
+
 impl<D: fmt::Display> Displayable for D {
     fn display(&self) -> Displayer<'_, D> {
         Displayer(self)
     }
 }
 
-impl<'a> WeftRenderable for &'a str {
-    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
+impl WeftRenderable for &str {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
         target.text(self)
     }
 }
 
 impl WeftRenderable for String {
-    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
         target.text(self)
     }
 }
 
+impl WeftRenderable for Cow<'_, str> {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        target.text(self)
+    }
+}
+
+/// Renders nothing for `None`.
+impl<T: WeftRenderable> WeftRenderable for Option<T> {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        match self {
+            Some(it) => it.render_to(target),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Renders each item in turn.
+impl<T: WeftRenderable> WeftRenderable for [T] {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        for it in self {
+            it.render_to(target)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: WeftRenderable, const N: usize> WeftRenderable for [T; N] {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        self[..].render_to(target)
+    }
+}
+
+impl<T: WeftRenderable> WeftRenderable for Vec<T> {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        self[..].render_to(target)
+    }
+}
+
+impl<T: WeftRenderable + ?Sized> WeftRenderable for Box<T> {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        (**self).render_to(target)
+    }
+}
+
+impl<T: WeftRenderable + ?Sized> WeftRenderable for Rc<T> {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        (**self).render_to(target)
+    }
+}
+
+impl<T: WeftRenderable + ?Sized> WeftRenderable for Arc<T> {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        (**self).render_to(target)
+    }
+}
+
+/// Renders each member of a tuple in turn.
+macro_rules! tuple_renderable {
+    ($($name:ident),+) => {
+        impl<$($name: WeftRenderable),+> WeftRenderable for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+                let ($($name,)+) = self;
+                $($name.render_to(target)?;)+
+                Ok(())
+            }
+        }
+    };
+}
+
+tuple_renderable!(A);
+tuple_renderable!(A, B);
+tuple_renderable!(A, B, C);
+tuple_renderable!(A, B, C, D);
+tuple_renderable!(A, B, C, D, E);
+tuple_renderable!(A, B, C, D, E, F);
+tuple_renderable!(A, B, C, D, E, F, G);
+tuple_renderable!(A, B, C, D, E, F, G, H);
+
+impl<S: AsRef<str>> WeftRenderable for PreEscaped<S> {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        target.raw_html(self.0.as_ref())
+    }
+}
+
 impl<'a, D: fmt::Display> WeftRenderable for Displayer<'a, D> {
-    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
-        target.text(&self.to_string())
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        render_display(self.0, target)
+    }
+}
+
+/// Numbers are formatted via a buffer on the stack, rather than allocating.
+macro_rules! display_renderable {
+    ($($ty:ty),+) => {
+        $(
+            impl WeftRenderable for $ty {
+                fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+                    render_display(self, target)
+                }
+            }
+        )+
+    };
+}
+
+display_renderable!(i8, i16, i32, i64, i128, isize);
+display_renderable!(u8, u16, u32, u64, u128, usize);
+display_renderable!(f32, f64);
+
+impl WeftRenderable for bool {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        target.text(if *self { "true" } else { "false" })
+    }
+}
+
+impl WeftRenderable for char {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
+        target.text(self.encode_utf8(&mut [0; 4]))
+    }
+}
+
+/// Writes `value` to `target` as text, without allocating.
+fn render_display(value: &impl fmt::Display, target: &mut impl RenderTarget) -> Result<(), Error> {
+    let mut writer = TextWriter {
+        target,
+        buf: [0; TEXT_BUFFER_SIZE],
+        len: 0,
+        error: None,
+    };
+    let res = write!(writer, "{}", value).and_then(|()| writer.flush());
+    match (res, writer.error) {
+        (Ok(()), _) => Ok(()),
+        (Err(_), Some(err)) => Err(err),
+        (Err(err), None) => Err(Error::render(err)),
+    }
+}
+
+/// Large enough for any integer, and most floats.
+const TEXT_BUFFER_SIZE: usize = 64;
+
+/// Adapts a [`RenderTarget`] to [`fmt::Write`], gathering text into a buffer
+/// so that short values are written with a single call to `text`.
+struct TextWriter<'t, T> {
+    target: &'t mut T,
+    buf: [u8; TEXT_BUFFER_SIZE],
+    len: usize,
+    // The error from the target, as `fmt::Error` cannot carry it.
+    error: Option<Error>,
+}
+
+impl<T: RenderTarget> TextWriter<'_, T> {
+    fn record(&mut self, res: Result<(), Error>) -> fmt::Result {
+        res.map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+
+    fn flush(&mut self) -> fmt::Result {
+        let len = std::mem::take(&mut self.len);
+        if len == 0 {
+            return Ok(());
+        }
+        // We only ever copy whole `str`s into the buffer.
+        let s = std::str::from_utf8(&self.buf[..len]).map_err(|_| fmt::Error)?;
+        let res = self.target.text(s);
+        self.record(res)
+    }
+}
+
+impl<T: RenderTarget> fmt::Write for TextWriter<'_, T> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.len + s.len() > TEXT_BUFFER_SIZE {
+            self.flush()?;
+        }
+        if s.len() > TEXT_BUFFER_SIZE {
+            let res = self.target.text(s);
+            return self.record(res);
+        }
+        self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
     }
 }
 
-impl<'a, D: fmt::Display> ToString for Displayer<'a, D> {
-    fn to_string(&self) -> String {
-        format!("{}", self.0)
+impl<'a, D: fmt::Display> fmt::Display for Displayer<'a, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
     }
 }
@@ -17,13 +17,23 @@ This module provides runtime support for `weft` templates.
 ```
 */
 
+mod error;
 mod extensions;
+mod loops;
 mod template;
+mod urls;
 
+pub use crate::error::{Error, ErrorKind, ExprError};
+pub use crate::extensions::{
+    is_event_handler_attribute, PreEscaped, ToAttrValue, ToEventHandlerAttr,
+};
+pub use crate::loops::{LoopInfo, LoopIter};
 pub use crate::template::*;
+pub use crate::urls::{url_part_for_attribute, AttrValue, ToUrlAttr, UrlPart};
 pub use weft_derive::WeftRenderable;
 
 /// A module for things that should be in-scope by default in a template expression.
 pub mod prelude {
     pub use crate::extensions::*;
+    pub use crate::urls::TrustedUrl;
 }
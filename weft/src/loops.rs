@@ -0,0 +1,79 @@
+//! Support for the `weft-loop` directive, which describes each iteration of a `weft-for`.
+use std::iter::Peekable;
+
+/// Describes the position of the current item within a `weft-for` loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopInfo {
+    index: usize,
+    last: bool,
+    length: Option<usize>,
+}
+
+impl LoopInfo {
+    /// The zero-based index of the current item.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    /// The one-based index of the current item.
+    pub fn index1(&self) -> usize {
+        self.index + 1
+    }
+    /// True for the first item.
+    pub fn first(&self) -> bool {
+        self.index == 0
+    }
+    /// True for the last item.
+    pub fn last(&self) -> bool {
+        self.last
+    }
+    /// True when the zero-based index is even.
+    pub fn even(&self) -> bool {
+        self.index & 1 == 0
+    }
+    /// True when the zero-based index is odd.
+    pub fn odd(&self) -> bool {
+        !self.even()
+    }
+    /// The total number of items, if the iterator reports it exactly.
+    pub fn length(&self) -> Option<usize> {
+        self.length
+    }
+}
+
+/// Wraps an iterator to pair each item with its [`LoopInfo`].
+pub struct LoopIter<I: Iterator> {
+    inner: Peekable<I>,
+    index: usize,
+    length: Option<usize>,
+}
+
+impl<I: Iterator> LoopIter<I> {
+    /// Wraps `iter`. The only lookahead is a single item, to find the last.
+    pub fn new(iter: impl IntoIterator<IntoIter = I>) -> Self {
+        let inner = iter.into_iter();
+        let length = match inner.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower),
+            _ => None,
+        };
+        LoopIter {
+            inner: inner.peekable(),
+            index: 0,
+            length,
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for LoopIter<I> {
+    type Item = (I::Item, LoopInfo);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next()?;
+        let info = LoopInfo {
+            index: self.index,
+            last: self.inner.peek().is_none(),
+            length: self.length,
+        };
+        self.index += 1;
+        Some((item, info))
+    }
+}
@@ -1,7 +1,11 @@
-use std::{borrow::Cow, io};
+use std::{borrow::Cow, fmt, io, ops::Range};
 
 use v_htmlescape::escape;
 
+use crate::error::Error;
+use crate::extensions::is_event_handler_attribute;
+use crate::urls::{url_part_for_attribute, ToUrlAttr};
+
 /// An internal representation of a qualified name, such as a tag or attribute.
 /// Does not currently support namespaces.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -23,8 +27,56 @@ impl<'a> QName<'a> {
     fn as_bytes(&self) -> &[u8] {
         self.0.as_bytes()
     }
+
+    /// Returns true if this names an HTML5 void element, such as `br` or
+    /// `img`, which may never have content nor an end tag.
+    pub fn is_void(&self) -> bool {
+        VOID_ELEMENTS
+            .iter()
+            .any(|v| v.eq_ignore_ascii_case(&self.0))
+    }
+
+    fn is_valid_element_name(&self) -> bool {
+        let mut chars = self.0.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() => {}
+            _ => return false,
+        }
+        chars.all(|c| !c.is_ascii() || c.is_ascii_alphanumeric() || "-_.:".contains(c))
+    }
+
+    fn is_valid_attribute_name(&self) -> bool {
+        !self.0.is_empty()
+            && self
+                .0
+                .chars()
+                .all(|c| !c.is_whitespace() && !c.is_control() && !"\"'<>/=".contains(c))
+    }
+
+    fn check_element_name(&self) -> Result<(), Error> {
+        if !self.is_valid_element_name() {
+            return Err(Error::render(format!("Invalid element name: {:?}", self.0)));
+        }
+        Ok(())
+    }
+
+    fn check_attribute_name(&self) -> Result<(), Error> {
+        if !self.is_valid_attribute_name() {
+            return Err(Error::render(format!(
+                "Invalid attribute name: {:?}",
+                self.0
+            )));
+        }
+        Ok(())
+    }
 }
 
+/// The elements that the HTML5 serialization algorithm writes without an end tag.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
 /// An attribute name and value pair.
 #[derive(Debug)]
 pub struct AttrPair<'n, 'v> {
@@ -36,65 +88,383 @@ pub struct AttrPair<'n, 'v> {
 ///
 pub trait RenderTarget {
     /// Open an element with the given name and attributes.
-    fn start_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), io::Error>;
-    /// Write plain text content.
-    fn text(&mut self, content: &str) -> Result<(), io::Error>;
+    fn start_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), Error>;
+    /// Write plain text content. Inside a `<script>` or `<style>` element,
+    /// this is escaped so that it cannot end a string literal that the
+    /// template places it within.
+    fn text(&mut self, content: &str) -> Result<(), Error>;
+    /// Write markup verbatim, without any escaping. The caller is responsible
+    /// for ensuring that `html` is well formed and safe to include.
+    ///
+    /// By default, this writes `html` as escaped text; targets that can write
+    /// markup should override it.
+    fn raw_html(&mut self, html: &str) -> Result<(), Error> {
+        self.text(html)
+    }
+    /// Write text from the template itself verbatim inside a raw text element,
+    /// such as `<script>` or `<style>`. Outside of those, this is equivalent
+    /// to `text`, which is also the default.
+    fn raw_text(&mut self, content: &str) -> Result<(), Error> {
+        self.text(content)
+    }
     /// Close an element.
-    fn end_element(&mut self, name: QName) -> Result<(), io::Error>;
+    fn end_element(&mut self, name: QName) -> Result<(), Error>;
+    /// Write a comment. Implementations must ensure that the content cannot
+    /// terminate the comment early. By default, comments are dropped.
+    fn comment(&mut self, _content: &str) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Write the HTML5 document type declaration, ie: `<!DOCTYPE html>`.
+    /// By default, this writes nothing.
+    fn doctype(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Emit an element that has no content, such as a void element like `<br>`.
+    /// By default, this opens and immediately closes the element.
+    fn void_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), Error> {
+        self.start_element_attrs(name.clone(), attrs)?;
+        self.end_element(name)
+    }
 }
 
 /// This is designed to be implemented via the `weft_derive` crate,
 /// but can be implemented manually for special cases.
 pub trait WeftRenderable {
     /// Outputs a representation of this object to the target.
-    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error>;
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error>;
 }
 
-impl<'a, T: RenderTarget> RenderTarget for &'a mut T {
-    fn start_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), io::Error> {
+impl<T: RenderTarget> RenderTarget for &mut T {
+    fn start_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), Error> {
         (**self).start_element_attrs(name, attrs)
     }
-    fn text(&mut self, content: &str) -> Result<(), io::Error> {
+    fn text(&mut self, content: &str) -> Result<(), Error> {
         (**self).text(content)
     }
-    fn end_element(&mut self, name: QName) -> Result<(), io::Error> {
+    fn raw_html(&mut self, html: &str) -> Result<(), Error> {
+        (**self).raw_html(html)
+    }
+    fn raw_text(&mut self, content: &str) -> Result<(), Error> {
+        (**self).raw_text(content)
+    }
+    fn end_element(&mut self, name: QName) -> Result<(), Error> {
         (**self).end_element(name)
     }
+    fn comment(&mut self, content: &str) -> Result<(), Error> {
+        (**self).comment(content)
+    }
+    fn doctype(&mut self) -> Result<(), Error> {
+        (**self).doctype()
+    }
+    fn void_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), Error> {
+        (**self).void_element_attrs(name, attrs)
+    }
 }
 
-impl<'a, R: WeftRenderable> WeftRenderable for &'a R {
-    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
+impl<R: WeftRenderable + ?Sized> WeftRenderable for &R {
+    fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), Error> {
         (**self).render_to(target)
     }
 }
 
-struct Html5Ser<T>(T);
+/// Elements whose content is not parsed as markup, and so needs to be
+/// written without HTML escaping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RawTextContext {
+    Script,
+    Style,
+}
+
+impl RawTextContext {
+    fn for_element(name: &QName) -> Option<Self> {
+        if name.0.eq_ignore_ascii_case("script") {
+            Some(RawTextContext::Script)
+        } else if name.0.eq_ignore_ascii_case("style") {
+            Some(RawTextContext::Style)
+        } else {
+            None
+        }
+    }
+
+    fn end_tag(&self) -> &'static str {
+        match self {
+            RawTextContext::Script => "</script",
+            RawTextContext::Style => "</style",
+        }
+    }
+}
+
+/// The elements currently open, so that we can report where an error occurred.
+/// Names are kept in a single buffer that is re-used as elements are opened
+/// and closed, so that successful rendering does not allocate per element.
+#[derive(Debug, Default)]
+struct ElementPath {
+    // The names of each distinct child seen within the open elements (and at
+    // the top level), back to back.
+    names: String,
+    // The number of children seen of each name, in the order that the names
+    // were first seen.
+    seen: Vec<SeenName>,
+    open: Vec<OpenElement>,
+}
+
+#[derive(Debug)]
+struct SeenName {
+    name: Range<usize>,
+    count: usize,
+}
+
+#[derive(Debug)]
+struct OpenElement {
+    name: Range<usize>,
+    // The one-based index of this element amongst its siblings of the same name.
+    index: usize,
+    // Where the names of this element's children begin, in `seen` and `names`.
+    seen_start: usize,
+    names_start: usize,
+}
+
+impl ElementPath {
+    fn push(&mut self, name: &str) {
+        let siblings_start = self.open.last().map_or(0, |parent| parent.seen_start);
+        let names = &self.names;
+        let sibling = self.seen[siblings_start..]
+            .iter_mut()
+            .find(|seen| &names[seen.name.clone()] == name);
+        let (name, index) = match sibling {
+            Some(seen) => {
+                seen.count += 1;
+                (seen.name.clone(), seen.count)
+            }
+            None => {
+                let start = self.names.len();
+                self.names.push_str(name);
+                let name = start..self.names.len();
+                self.seen.push(SeenName {
+                    name: name.clone(),
+                    count: 1,
+                });
+                (name, 1)
+            }
+        };
+        self.open.push(OpenElement {
+            name,
+            index,
+            seen_start: self.seen.len(),
+            names_start: self.names.len(),
+        });
+    }
+
+    fn pop(&mut self) {
+        if let Some(elt) = self.open.pop() {
+            self.seen.truncate(elt.seen_start);
+            self.names.truncate(elt.names_start);
+        }
+    }
+}
+
+impl fmt::Display for ElementPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, elt) in self.open.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" > ")?;
+            }
+            f.write_str(&self.names[elt.name.clone()])?;
+            if elt.index > 1 {
+                write!(f, "[{}]", elt.index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Html5Ser<T> {
+    out: T,
+    path: ElementPath,
+    // Set whilst we are between the start and end of a void element, where
+    // no content is permitted.
+    in_void: bool,
+    // Set whilst we are inside a raw text element, where only text is permitted.
+    raw_text: Option<RawTextContext>,
+}
+
+impl<T> Html5Ser<T> {
+    fn new(out: T) -> Self {
+        Html5Ser {
+            out,
+            path: ElementPath::default(),
+            in_void: false,
+            raw_text: None,
+        }
+    }
+
+    fn check_content_allowed(&self) -> Result<(), Error> {
+        if self.in_void {
+            return Err(Error::render("Void elements may not have content"));
+        }
+        Ok(())
+    }
+
+    fn check_markup_allowed(&self) -> Result<(), Error> {
+        self.check_content_allowed()?;
+        if let Some(context) = self.raw_text {
+            return Err(Error::render(format!(
+                "{:?} elements may only contain text",
+                context
+            )));
+        }
+        Ok(())
+    }
+}
 
-impl<'a, T: 'a + io::Write> RenderTarget for Html5Ser<T> {
-    fn start_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), io::Error> {
-        self.0.write_all(b"<")?;
-        self.0.write_all(name.0.as_bytes())?;
+impl<T: io::Write> RenderTarget for Html5Ser<T> {
+    fn start_element_attrs(&mut self, name: QName, attrs: &[&AttrPair]) -> Result<(), Error> {
+        self.check_markup_allowed()?;
+        name.check_element_name()?;
+        for attr in attrs {
+            attr.name.check_attribute_name()?;
+        }
+        self.path.push(&name.0);
+        self.in_void = name.is_void();
+        self.raw_text = RawTextContext::for_element(&name);
+        self.out.write_all(b"<")?;
+        self.out.write_all(name.0.as_bytes())?;
 
         for attr in attrs {
-            // TODO: Escaping!
-            self.0.write_all(b" ")?;
-            self.0.write_all(attr.name.as_bytes())?;
-            self.0.write_all(b"=")?;
-            write!(self.0, "\"{}\"", escape(&attr.value))?;
+            self.out.write_all(b" ")?;
+            self.out.write_all(attr.name.as_bytes())?;
+            // An empty value is equivalent to a bare attribute, so we minimise it.
+            if !attr.value.is_empty() {
+                self.out.write_all(b"=")?;
+                write!(self.out, "\"{}\"", escape(&attr.value))?;
+            }
+        }
+        self.out.write_all(b">")?;
+        Ok(())
+    }
+    fn text(&mut self, content: &str) -> Result<(), Error> {
+        self.check_content_allowed()?;
+        match self.raw_text {
+            None => write!(self.out, "{}", escape(content))?,
+            Some(RawTextContext::Script) => write_js_string_escaped(&mut self.out, content)?,
+            Some(RawTextContext::Style) => write_css_escaped(&mut self.out, content)?,
+        }
+        Ok(())
+    }
+    fn raw_html(&mut self, html: &str) -> Result<(), Error> {
+        self.check_content_allowed()?;
+        if self.raw_text.is_some() {
+            return self.raw_text(html);
         }
-        self.0.write_all(b">")?;
+        self.out.write_all(html.as_bytes())?;
         Ok(())
     }
-    fn text(&mut self, content: &str) -> Result<(), io::Error> {
-        write!(self.0, "{}", escape(content))?;
+    fn raw_text(&mut self, content: &str) -> Result<(), Error> {
+        self.check_content_allowed()?;
+        let context = match self.raw_text {
+            Some(context) => context,
+            None => return self.text(content),
+        };
+        let end_tag = context.end_tag();
+        let terminates = content
+            .as_bytes()
+            .windows(end_tag.len())
+            .any(|w| w.eq_ignore_ascii_case(end_tag.as_bytes()));
+        if terminates {
+            return Err(Error::render(format!(
+                "Raw text may not contain {:?}",
+                end_tag
+            )));
+        }
+        self.out.write_all(content.as_bytes())?;
         Ok(())
     }
-    fn end_element(&mut self, name: QName) -> Result<(), io::Error> {
-        self.0.write_all(b"</")?;
-        self.0.write_all(name.0.as_bytes())?;
-        self.0.write_all(b">")?;
+    fn end_element(&mut self, name: QName) -> Result<(), Error> {
+        name.check_element_name()?;
+        self.path.pop();
+        self.raw_text = None;
+        if name.is_void() {
+            self.in_void = false;
+            return Ok(());
+        }
+        self.out.write_all(b"</")?;
+        self.out.write_all(name.0.as_bytes())?;
+        self.out.write_all(b">")?;
         Ok(())
     }
+    fn comment(&mut self, content: &str) -> Result<(), Error> {
+        self.check_markup_allowed()?;
+        self.out.write_all(b"<!--")?;
+        self.out.write_all(escape_comment(content).as_bytes())?;
+        self.out.write_all(b"-->")?;
+        Ok(())
+    }
+    fn doctype(&mut self) -> Result<(), Error> {
+        self.check_markup_allowed()?;
+        self.out.write_all(b"<!DOCTYPE html>")?;
+        Ok(())
+    }
+}
+
+/// Writes `content` such that it is safe to include within a Javascript or
+/// JSON string literal. Anything that could end the string or the enclosing
+/// `<script>` element is written as a `\uNNNN` escape, which both languages
+/// accept. Numbers and booleans are written as they are, so may also be
+/// interpolated outside of a string.
+pub(crate) fn write_js_string_escaped(
+    out: &mut impl io::Write,
+    content: &str,
+) -> Result<(), io::Error> {
+    for c in content.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' | '-' | '+' => write!(out, "{}", c)?,
+            c if c.is_ascii() || c == '\u{2028}' || c == '\u{2029}' => {
+                write!(out, "\\u{:04x}", c as u32)?
+            }
+            c => write!(out, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+/// Writes `content` such that it is safe to include within a CSS string or
+/// identifier, by writing any ASCII punctuation as a hex escape.
+fn write_css_escaped(out: &mut impl io::Write, content: &str) -> Result<(), io::Error> {
+    for c in content.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => write!(out, "{}", c)?,
+            c if c.is_ascii() => write!(out, "\\{:x} ", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+/// Ensures that `content` is valid as the text of a comment, by breaking up
+/// any `--` sequences, and padding content that would otherwise run into the
+/// comment delimiters.
+fn escape_comment(content: &str) -> Cow<'_, str> {
+    let unsafe_start = content.starts_with('>') || content.starts_with("->");
+    if !content.contains("--") && !unsafe_start && !content.ends_with('-') {
+        return content.into();
+    }
+
+    let mut escaped = String::with_capacity(content.len() + 2);
+    if unsafe_start {
+        escaped.push(' ');
+    }
+    let mut prev = None;
+    for c in content.chars() {
+        if c == '-' && prev == Some('-') {
+            escaped.push(' ');
+        }
+        escaped.push(c);
+        prev = Some(c);
+    }
+    if content.ends_with('-') {
+        escaped.push(' ');
+    }
+    escaped.into()
 }
 
 impl<'n, 'v> AttrPair<'n, 'v> {
@@ -104,15 +474,59 @@ impl<'n, 'v> AttrPair<'n, 'v> {
     }
 }
 
+/// Merges attributes computed at runtime, such as by a `weft-attrs`
+/// directive, into `attrs`.
+///
+/// Values for `class` are joined with a space; for any other name, the later
+/// value replaces the earlier one. Values for URL-bearing attributes are
+/// filtered as for interpolated values, and event handler attributes, such as
+/// `onclick`, are rejected.
+pub fn merge_attrs<'n, 'v, K: AsRef<str>, V: AsRef<str>>(
+    mut attrs: Vec<AttrPair<'n, 'v>>,
+    extra: impl IntoIterator<Item = (K, V)>,
+) -> Result<Vec<AttrPair<'n, 'v>>, Error> {
+    for (name, value) in extra {
+        let (name, value) = (name.as_ref(), value.as_ref());
+        if is_event_handler_attribute(name) {
+            return Err(Error::render(format!(
+                "Cannot set event handler attribute {:?} dynamically",
+                name
+            )));
+        }
+        let value = match url_part_for_attribute(name) {
+            Some(part) => value.to_url_attr(part).into_owned(),
+            None => value.to_string(),
+        };
+
+        match attrs
+            .iter_mut()
+            .find(|a| a.name.0.eq_ignore_ascii_case(name))
+        {
+            Some(existing) if name.eq_ignore_ascii_case("class") && !existing.value.is_empty() => {
+                existing.value = format!("{} {}", existing.value, value).into();
+            }
+            Some(existing) => existing.value = value.into(),
+            None => attrs.push(AttrPair::new(name.to_string().into(), value.into())),
+        }
+    }
+    Ok(attrs)
+}
+
 /// Renders the template in `widget` to the writer `out`.
-pub fn render_writer<R: WeftRenderable, W: io::Write>(widget: R, out: W) -> Result<(), io::Error> {
-    let mut ser = Html5Ser(out);
-    widget.render_to(&mut ser)?;
+///
+/// Failures to write to `out` are reported as errors of kind
+/// [`ErrorKind::Io`](crate::ErrorKind::Io); anything else as
+/// [`ErrorKind::Render`](crate::ErrorKind::Render).
+pub fn render_writer<R: WeftRenderable, W: io::Write>(widget: R, out: W) -> Result<(), Error> {
+    let mut ser = Html5Ser::new(out);
+    widget
+        .render_to(&mut ser)
+        .map_err(|err| err.at_element_path(|| ser.path.to_string()))?;
     Ok(())
 }
 
 /// Renders the template in `widget` to a new String.
-pub fn render_to_string<R: WeftRenderable>(widget: R) -> Result<String, io::Error> {
+pub fn render_to_string<R: WeftRenderable>(widget: R) -> Result<String, Error> {
     let mut out = Vec::new();
     render_writer(widget, &mut out)?;
     Ok(String::from_utf8_lossy(&out).into_owned())
@@ -0,0 +1,266 @@
+//! Escaping for values interpolated into URL-bearing attributes, such as `href`.
+use std::{borrow::Cow, fmt};
+
+/// Written in place of a URL whose scheme is not known to be safe.
+const UNSAFE_URL: &str = "about:invalid#weft-unsafe-url";
+
+/// Schemes that may be used at the start of an interpolated URL.
+const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto", "tel"];
+
+/// Attributes whose values are URLs.
+const URL_ATTRIBUTES: &[&str] = &[
+    "action",
+    "background",
+    "cite",
+    "codebase",
+    "data",
+    "formaction",
+    "href",
+    "icon",
+    "longdesc",
+    "manifest",
+    "poster",
+    "profile",
+    "src",
+    "usemap",
+];
+
+/// Attributes whose values are lists of candidate URLs.
+const SRCSET_ATTRIBUTES: &[&str] = &["srcset", "imagesrcset"];
+
+/// Returns how a whole value for the attribute `name` should be treated, or
+/// `None` if it is not URL-bearing.
+pub fn url_part_for_attribute(name: &str) -> Option<UrlPart> {
+    let matches = |names: &[&str]| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+    if matches(URL_ATTRIBUTES) {
+        Some(UrlPart::Start)
+    } else if matches(SRCSET_ATTRIBUTES) {
+        Some(UrlPart::Srcset)
+    } else {
+        None
+    }
+}
+
+/// Where an interpolated value appears within a URL-bearing attribute. This
+/// is determined by the template literal text preceding the value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UrlPart {
+    /// The value begins the URL, and so determines the scheme.
+    Start,
+    /// The value follows a literal prefix, but precedes any query or fragment.
+    Path,
+    /// The value is within the query or fragment.
+    Query,
+    /// The value is a candidate URL within a `srcset` attribute.
+    Srcset,
+}
+
+/// A URL that is trusted to be safe, so will not be filtered when
+/// interpolated into an attribute such as `href`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustedUrl<S>(S);
+
+impl<S: AsRef<str>> TrustedUrl<S> {
+    /// Marks `url` as trusted. The caller must ensure it does not use an
+    /// unsafe scheme, such as `javascript:`.
+    pub fn new(url: S) -> Self {
+        TrustedUrl(url)
+    }
+}
+
+impl<S: AsRef<str>> AsRef<str> for TrustedUrl<S> {
+    fn as_ref(&self) -> &str {
+        self.0.as_ref()
+    }
+}
+
+/// Converts a value interpolated into a URL-bearing attribute into a form
+/// that is safe to include, given its position in the URL.
+pub trait ToUrlAttr {
+    /// Returns the filtered and encoded value.
+    fn to_url_attr(&self, part: UrlPart) -> Cow<'_, str>;
+    /// Returns the value as written into an attribute that is not URL-bearing.
+    fn to_plain_attr(&self) -> Cow<'_, str>;
+    /// Whether the value is trusted to choose its own scheme. Defaults to
+    /// `false`.
+    fn is_trusted_url(&self) -> bool {
+        false
+    }
+}
+
+impl<D: fmt::Display> ToUrlAttr for D {
+    fn to_url_attr(&self, part: UrlPart) -> Cow<'_, str> {
+        let value = self.to_string();
+        let encoded = match part {
+            UrlPart::Start => normalize(filter_scheme(&value), false),
+            UrlPart::Path => normalize(&value, false),
+            UrlPart::Query => encode_component(&value),
+            UrlPart::Srcset => normalize(filter_scheme(&value), true),
+        };
+        encoded.into()
+    }
+
+    fn to_plain_attr(&self) -> Cow<'_, str> {
+        self.to_string().into()
+    }
+}
+
+impl<S: AsRef<str>> ToUrlAttr for TrustedUrl<S> {
+    fn to_url_attr(&self, _: UrlPart) -> Cow<'_, str> {
+        self.0.as_ref().into()
+    }
+
+    fn to_plain_attr(&self) -> Cow<'_, str> {
+        self.0.as_ref().into()
+    }
+
+    fn is_trusted_url(&self) -> bool {
+        true
+    }
+}
+
+/// Assembles an attribute value from template text and interpolated values.
+/// This is used by `weft_derive`, so that URL-bearing attributes are
+/// filtered the same way as by [`merge_attrs`](crate::merge_attrs).
+///
+/// The scheme of a URL is only taken as given by the template when the
+/// template text before any interpolation ends it (ie: contains one of `:`,
+/// `/`, `?` or `#`). Otherwise, the whole value is checked once assembled, so
+/// that eg: `java{{ self.0 }}` cannot be used to build a `javascript:` URL.
+#[derive(Debug)]
+pub struct AttrValue {
+    url: Option<UrlPart>,
+    // Set once the template text has determined the scheme.
+    scheme_fixed: bool,
+    // Set once an untrusted value has been interpolated.
+    interpolated: bool,
+    value: String,
+}
+
+impl AttrValue {
+    /// Starts the value of the attribute `name`.
+    pub fn new(name: &str) -> Self {
+        AttrValue {
+            url: url_part_for_attribute(name),
+            scheme_fixed: false,
+            interpolated: false,
+            value: String::new(),
+        }
+    }
+
+    /// Appends template text verbatim.
+    pub fn literal(mut self, text: &str) -> Self {
+        if let Some(part) = self.url {
+            if part != UrlPart::Srcset && !self.interpolated && text.contains([':', '/', '?', '#'])
+            {
+                self.scheme_fixed = true;
+            }
+            self.url = Some(part.after_literal(text));
+        }
+        self.value.push_str(text);
+        self
+    }
+
+    /// Appends an interpolated value, filtered and encoded according to its
+    /// position.
+    pub fn value<V: ToUrlAttr + ?Sized>(mut self, value: &V) -> Self {
+        match self.url {
+            Some(part) => {
+                self.interpolated |= !value.is_trusted_url();
+                self.value.push_str(&value.to_url_attr(part));
+            }
+            None => self.value.push_str(&value.to_plain_attr()),
+        }
+        self
+    }
+
+    /// Returns the assembled value.
+    pub fn finish(self) -> String {
+        let unsafe_scheme = match self.url {
+            _ if self.scheme_fixed || !self.interpolated => false,
+            Some(UrlPart::Srcset) => self.value.split(',').any(|candidate| {
+                has_unsafe_scheme(candidate.trim_start_matches(is_stripped_url_prefix))
+            }),
+            Some(_) => has_unsafe_scheme(&self.value),
+            None => false,
+        };
+        if unsafe_scheme {
+            UNSAFE_URL.to_string()
+        } else {
+            self.value
+        }
+    }
+}
+
+impl UrlPart {
+    /// Where a value following the template text `literal` appears.
+    fn after_literal(self, literal: &str) -> Self {
+        match self {
+            UrlPart::Srcset | UrlPart::Query => self,
+            _ if literal.contains(['?', '#']) => UrlPart::Query,
+            // Browsers skip leading whitespace and control characters when
+            // finding the scheme, so the value still starts the URL.
+            UrlPart::Start if literal.chars().all(is_stripped_url_prefix) => self,
+            _ => UrlPart::Path,
+        }
+    }
+}
+
+/// Returns true for characters that browsers strip from the start of a URL
+/// before looking for the scheme: ASCII whitespace and C0 controls.
+fn is_stripped_url_prefix(c: char) -> bool {
+    c <= ' '
+}
+
+fn filter_scheme(url: &str) -> &str {
+    if has_unsafe_scheme(url) {
+        UNSAFE_URL
+    } else {
+        url
+    }
+}
+
+fn has_unsafe_scheme(url: &str) -> bool {
+    let trimmed = url.trim_start_matches(is_stripped_url_prefix);
+    let scheme_end = trimmed.find([':', '/', '?', '#']);
+    match scheme_end {
+        Some(idx) if trimmed[idx..].starts_with(':') => {
+            let scheme = &trimmed[..idx];
+            !SAFE_SCHEMES.iter().any(|s| s.eq_ignore_ascii_case(scheme))
+        }
+        _ => false,
+    }
+}
+
+/// Percent-encodes anything that may not appear in a URL, leaving the
+/// reserved delimiters and existing escapes as they are.
+fn normalize(url: &str, in_srcset: bool) -> String {
+    percent_encode(url, |b| match b {
+        b',' => !in_srcset,
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => true,
+        b'-' | b'.' | b'_' | b'~' | b'%' => true,
+        b':' | b'/' | b'?' | b'#' | b'[' | b']' | b'@' => true,
+        b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b';' | b'=' => true,
+        _ => false,
+    })
+}
+
+/// Percent-encodes everything but unreserved characters.
+fn encode_component(value: &str) -> String {
+    percent_encode(
+        value,
+        |b| matches!(b, b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~'),
+    )
+}
+
+fn percent_encode(value: &str, is_allowed: impl Fn(u8) -> bool) -> String {
+    let mut out = String::with_capacity(value.len());
+    for &b in value.as_bytes() {
+        if is_allowed(b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
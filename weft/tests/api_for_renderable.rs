@@ -8,7 +8,7 @@ fn should_render_trivial_example() {
     struct TrivialExample;
     // This simulates what a template of the form `<p>Hello</p>` should compile to.
     impl WeftRenderable for TrivialExample {
-        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
             target.start_element_attrs("p".into(), &[])?;
             target.text("Hello")?;
             target.end_element("p".into())?;
@@ -26,12 +26,49 @@ fn should_render_trivial_example() {
     );
 }
 
+#[test]
+fn should_only_require_elements_and_text_from_render_targets() {
+    #[derive(Default)]
+    struct Outline(String);
+    impl RenderTarget for Outline {
+        fn start_element_attrs(&mut self, _: QName, _: &[&AttrPair]) -> Result<(), weft::Error> {
+            self.0.push('[');
+            Ok(())
+        }
+        fn text(&mut self, content: &str) -> Result<(), weft::Error> {
+            self.0.push_str(content);
+            Ok(())
+        }
+        fn end_element(&mut self, _: QName) -> Result<(), weft::Error> {
+            self.0.push(']');
+            Ok(())
+        }
+    }
+
+    struct Example;
+    impl WeftRenderable for Example {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.doctype()?;
+            target.start_element_attrs("p".into(), &[])?;
+            target.comment("Hidden")?;
+            target.raw_text("Raw ")?;
+            target.raw_html("<b>")?;
+            target.end_element("p".into())?;
+            Ok(())
+        }
+    }
+
+    let mut out = Outline::default();
+    Example.render_to(&mut out).expect("render_to");
+    assert_eq!(out.0, "[Raw <b>]");
+}
+
 #[test]
 fn should_render_attrs() {
     struct TrivialExample;
     // This simulates what a template of the form `<p>Hello</p>` should compile to.
     impl WeftRenderable for TrivialExample {
-        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
             target.start_element_attrs(
                 "p".into(),
                 &[&AttrPair::new("class".into(), "some-classes".into())],
@@ -58,7 +95,7 @@ fn render_supports_builtins() {
     struct TrivialExample;
     // This simulates what a template of the form `<p>Hello</p>` should compile to.
     impl WeftRenderable for TrivialExample {
-        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
             "Hello world!".render_to(target)?;
             Ok(())
         }
@@ -79,7 +116,7 @@ fn display_supports_renderable() {
     struct Displayable<D>(D);
     // This simulates what a template of the form `<p>Hello</p>` should compile to.
     impl<D: fmt::Display> WeftRenderable for Displayable<D> {
-        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
             use weft::prelude::*;
             self.0.display().render_to(target)?;
             Ok(())
@@ -96,7 +133,7 @@ fn display_supports_to_string() {
     struct Displayable<D>(D);
     // This simulates what a template of the form `<p>Hello</p>` should compile to.
     impl<D: fmt::Display> WeftRenderable for Displayable<D> {
-        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), io::Error> {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
             use weft::prelude::*;
             target.start_element_attrs(
                 "p".into(),
@@ -118,3 +155,340 @@ fn display_supports_to_string() {
         expected
     );
 }
+
+#[test]
+fn should_omit_end_tag_for_void_elements() {
+    struct VoidExample;
+    impl WeftRenderable for VoidExample {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.start_element_attrs("br".into(), &[])?;
+            target.end_element("br".into())?;
+            target
+                .void_element_attrs("input".into(), &[&AttrPair::new("name".into(), "q".into())])?;
+            Ok(())
+        }
+    }
+
+    let s = render_to_string(VoidExample).expect("render_to_string");
+    let expected = "<br><input name=\"q\">";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_reject_content_in_void_elements() {
+    struct VoidWithContent;
+    impl WeftRenderable for VoidWithContent {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.start_element_attrs("br".into(), &[])?;
+            target.text("Hello")?;
+            target.end_element("br".into())?;
+            Ok(())
+        }
+    }
+
+    let res = render_to_string(VoidWithContent);
+    assert!(res.is_err(), "Rendering should fail; got: {:?}", res);
+    assert_eq!(res.unwrap_err().kind(), ErrorKind::Render);
+}
+
+#[test]
+fn should_return_render_errors_to_caller() {
+    #[derive(Debug, PartialEq)]
+    struct MissingData(&'static str);
+    impl fmt::Display for MissingData {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Missing {}", self.0)
+        }
+    }
+    impl std::error::Error for MissingData {}
+
+    struct Failing;
+    impl WeftRenderable for Failing {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.start_element_attrs("p".into(), &[])?;
+            Err(weft::Error::render(MissingData("user")))
+        }
+    }
+
+    let err = render_to_string(Failing).expect_err("rendering should fail");
+    assert_eq!(err.kind(), ErrorKind::Render);
+    assert_eq!(
+        err.downcast_ref::<MissingData>(),
+        Some(&MissingData("user"))
+    );
+    assert_eq!(err.to_string(), "Rendering failed: Missing user at p");
+    assert_eq!(err.element_path(), Some("p"));
+}
+
+#[test]
+fn should_report_write_failures_as_io_errors() {
+    struct FailingWriter;
+    impl io::Write for FailingWriter {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let err = render_writer("Hello", FailingWriter).expect_err("rendering should fail");
+    assert!(err.is_io(), "Error should be from I/O: {:?}", err);
+    let io_err = err.downcast::<io::Error>().expect("io::Error");
+    assert_eq!(io_err.kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn should_escape_comment_delimiters() {
+    struct CommentExample(&'static str);
+    impl WeftRenderable for CommentExample {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.comment(self.0)?;
+            Ok(())
+        }
+    }
+
+    for content in &["a --> b", "->x", ">x", "x<!-", "a---b", "--!>"] {
+        let s = render_to_string(CommentExample(content)).expect("render_to_string");
+        let body = &s["<!--".len()..s.len() - "-->".len()];
+        assert!(
+            !body.contains("--") && !body.starts_with('>') && !body.starts_with("->"),
+            "Comment for {:?} should be escaped; got {:?}",
+            content,
+            s
+        );
+        assert!(
+            !body.ends_with('-'),
+            "Comment for {:?} should not end with a dash; got {:?}",
+            content,
+            s
+        );
+    }
+
+    let s = render_to_string(CommentExample(" licence ")).expect("render_to_string");
+    assert_eq!(s, "<!-- licence -->");
+}
+
+#[test]
+fn should_reject_raw_text_that_closes_script() {
+    struct ScriptExample;
+    impl WeftRenderable for ScriptExample {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.start_element_attrs("script".into(), &[])?;
+            target.raw_text("var x = 1;</SCRIPT><script>alert(1)")?;
+            target.end_element("script".into())?;
+            Ok(())
+        }
+    }
+
+    let res = render_to_string(ScriptExample);
+    assert!(res.is_err(), "Rendering should fail; got: {:?}", res);
+}
+
+#[test]
+fn should_reject_elements_inside_script() {
+    struct ScriptExample;
+    impl WeftRenderable for ScriptExample {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.start_element_attrs("script".into(), &[])?;
+            target.start_element_attrs("b".into(), &[])?;
+            target.end_element("b".into())?;
+            target.end_element("script".into())?;
+            Ok(())
+        }
+    }
+
+    let res = render_to_string(ScriptExample);
+    assert!(res.is_err(), "Rendering should fail; got: {:?}", res);
+}
+
+#[test]
+fn should_escape_raw_text_outside_raw_text_elements() {
+    struct RawExample;
+    impl WeftRenderable for RawExample {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.start_element_attrs("p".into(), &[])?;
+            target.raw_text("a && b")?;
+            target.end_element("p".into())?;
+            Ok(())
+        }
+    }
+
+    let s = render_to_string(RawExample).expect("render_to_string");
+    assert_eq!(s, "<p>a &amp;&amp; b</p>");
+}
+
+#[test]
+fn should_write_pre_escaped_markup_verbatim() {
+    struct Markdown;
+    impl WeftRenderable for Markdown {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.start_element_attrs("div".into(), &[])?;
+            PreEscaped::dangerously_trust("<p>Some <em>markdown</em></p>").render_to(target)?;
+            target.end_element("div".into())?;
+            Ok(())
+        }
+    }
+
+    let s = render_to_string(Markdown).expect("render_to_string");
+    assert_eq!(s, "<div><p>Some <em>markdown</em></p></div>");
+}
+
+#[test]
+fn should_reject_invalid_element_names() {
+    struct BadElement(&'static str);
+    impl WeftRenderable for BadElement {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.start_element_attrs(self.0.into(), &[])?;
+            target.end_element(self.0.into())?;
+            Ok(())
+        }
+    }
+
+    for name in &["", "1p", "p><script", "p onclick=x", "p/"] {
+        let res = render_to_string(BadElement(name));
+        assert!(
+            res.is_err(),
+            "Rendering {:?} should fail; got: {:?}",
+            name,
+            res
+        );
+    }
+
+    let s = render_to_string(BadElement("my-widget")).expect("render_to_string");
+    assert_eq!(s, "<my-widget></my-widget>");
+}
+
+#[test]
+fn should_reject_invalid_attribute_names() {
+    struct BadAttr(&'static str);
+    impl WeftRenderable for BadAttr {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            target.start_element_attrs("p".into(), &[&AttrPair::new(self.0.into(), "".into())])?;
+            target.end_element("p".into())?;
+            Ok(())
+        }
+    }
+
+    for name in &["", "a b", "x=\"y\"", "a>", "a/", "\"", "a\u{0}"] {
+        let res = render_to_string(BadAttr(name));
+        assert!(
+            res.is_err(),
+            "Rendering {:?} should fail; got: {:?}",
+            name,
+            res
+        );
+    }
+
+    let s = render_to_string(BadAttr("data-x")).expect("render_to_string");
+    assert_eq!(s, "<p data-x></p>");
+}
+
+#[test]
+fn merge_attrs_should_filter_urls() {
+    let attrs = merge_attrs(vec![], vec![("href", "javascript:alert(1)")]).expect("merge_attrs");
+
+    struct Link<'a>(Vec<AttrPair<'a, 'a>>);
+    impl<'a> WeftRenderable for Link<'a> {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            let attrs = self.0.iter().collect::<Vec<_>>();
+            target.start_element_attrs("a".into(), &attrs)?;
+            target.end_element("a".into())?;
+            Ok(())
+        }
+    }
+
+    let s = render_to_string(Link(attrs)).expect("render_to_string");
+    assert!(
+        !s.contains("javascript"),
+        "String {:?} should be filtered",
+        s
+    );
+}
+
+#[test]
+fn merge_attrs_should_reject_event_handlers() {
+    let res = merge_attrs(vec![], vec![("OnClick", "alert(1)")]);
+    assert!(res.is_err(), "Merging should fail; got: {:?}", res);
+
+    let attrs =
+        merge_attrs(vec![], vec![("日x", "1"), ("o", "2"), ("one", "3")]).expect("merge_attrs");
+    assert_eq!(attrs.len(), 3);
+}
+
+#[test]
+fn merge_attrs_should_match_names_case_insensitively() {
+    let attrs = merge_attrs(
+        vec![
+            AttrPair::new("class".into(), "a".into()),
+            AttrPair::new("id".into(), "x".into()),
+        ],
+        vec![("CLASS", "b"), ("ID", "y")],
+    )
+    .expect("merge_attrs");
+
+    struct Para<'a>(Vec<AttrPair<'a, 'a>>);
+    impl<'a> WeftRenderable for Para<'a> {
+        fn render_to(&self, target: &mut impl RenderTarget) -> Result<(), weft::Error> {
+            let attrs = self.0.iter().collect::<Vec<_>>();
+            target.start_element_attrs("p".into(), &attrs)?;
+            target.end_element("p".into())?;
+            Ok(())
+        }
+    }
+
+    let s = render_to_string(Para(attrs)).expect("render_to_string");
+    assert_eq!(s, "<p class=\"a b\" id=\"y\"></p>");
+}
+
+#[test]
+fn should_render_options() {
+    assert_eq!(render_to_string(Some("Hi")).expect("render"), "Hi");
+    assert_eq!(render_to_string(None::<&str>).expect("render"), "");
+}
+
+#[test]
+fn should_render_sequences_in_order() {
+    let items = vec!["a", "b", "c"];
+    assert_eq!(render_to_string(&items).expect("render"), "abc");
+    assert_eq!(render_to_string(&items[1..]).expect("render"), "bc");
+    assert_eq!(render_to_string(["x", "y"]).expect("render"), "xy");
+}
+
+#[test]
+fn should_render_heterogeneous_tuples() {
+    let s = render_to_string(("a < ", String::from("b"), Some("c"))).expect("render");
+    assert_eq!(s, "a &lt; bc");
+}
+
+#[test]
+fn should_render_through_smart_pointers() {
+    use std::borrow::Cow;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    let boxed: Box<[&str]> = vec!["a", "b"].into_boxed_slice();
+    assert_eq!(render_to_string(boxed).expect("render"), "ab");
+    assert_eq!(render_to_string(Rc::new("c")).expect("render"), "c");
+    assert_eq!(render_to_string(Arc::new("d")).expect("render"), "d");
+    assert_eq!(
+        render_to_string(Cow::<str>::Owned("<e>".into())).expect("render"),
+        "&lt;e&gt;"
+    );
+}
+
+#[test]
+fn should_render_primitives_directly() {
+    let s = render_to_string((42u8, ' ', -7i64, ' ', 1.5f64, ' ', true, '<')).expect("render");
+    assert_eq!(s, "42 -7 1.5 true&lt;");
+}
+
+#[test]
+fn should_render_long_displayed_values() {
+    let s = render_to_string(1e100f64).expect("render");
+    assert_eq!(s, format!("1{}", "0".repeat(100)));
+
+    let long = "<&>".repeat(40);
+    let s = render_to_string(weft::prelude::Displayable::display(&long)).expect("render");
+    assert_eq!(s, "&lt;&amp;&gt;".repeat(40));
+}
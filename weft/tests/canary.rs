@@ -38,7 +38,6 @@ fn should_allow_inline_source() {
     )
 }
 
-#[cfg(never)]
 #[test]
 fn should_render_entire_document() {
     #[derive(WeftRenderable)]
@@ -56,6 +55,31 @@ fn should_render_entire_document() {
     )
 }
 
+#[test]
+fn should_preserve_doctype_and_document_elements() {
+    #[derive(WeftRenderable)]
+    #[template(
+        source = "<!DOCTYPE html>\n<html lang=\"en\"><head><title>hi</title></head><body><p>Body</p></body></html>"
+    )]
+    struct Page;
+    let s = weft::render_to_string(Page).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<!DOCTYPE html><html lang=\"en\"><head><title>hi</title></head><body><p>Body</p></body></html>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_not_add_implied_elements_to_fragments() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<p>Fragment</p>")]
+    struct Fragment;
+    let s = weft::render_to_string(Fragment).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<p>Fragment</p>");
+}
+
 #[test]
 fn can_render_portion_of_document() {
     #[derive(WeftRenderable)]
@@ -223,8 +247,8 @@ fn should_support_polymorphism() {
 
 #[test]
 fn should_support_bare_attributes() {
-    // We ostensibly don't differentiate between a bare attribute and
-    // and empty value.
+    // We don't differentiate between a bare attribute and an empty value,
+    // and so render both in the minimised form.
     #[derive(WeftRenderable)]
     #[template(source = "<p some-thing></p>")]
     struct BareAttr;
@@ -232,7 +256,7 @@ fn should_support_bare_attributes() {
     let s = weft::render_to_string(BareAttr).expect("render_to_string");
     println!("{}", s);
 
-    let expected = " some-thing=";
+    let expected = "<p some-thing>";
     assert!(
         s.contains(expected),
         "String {:?} should contain {:?}",
@@ -295,12 +319,11 @@ fn should_correctly_parse_inline_with_entities() {
     )
 }
 
-#[cfg(never)]
 #[test]
-#[ignore]
 fn should_allow_disabling_inline_exprs() {
     #[derive(WeftRenderable)]
     #[template(source = "<p weft-inline-disable>Hello {{ self.0 }}!</p>")]
+    #[allow(dead_code)]
     struct Greeting2(String);
 
     let s = weft::render_to_string(Greeting2("world".into())).expect("render_to_string");
@@ -369,3 +392,861 @@ fn should_correctly_escape_content_in_attrs() {
         unwanted
     );
 }
+
+#[test]
+fn should_reject_content_given_to_void_elements() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<br weft-content=\"self.0\">")]
+    struct Break(&'static str);
+
+    let res = weft::render_to_string(Break("Hello"));
+    assert!(res.is_err(), "Rendering should fail; got: {:?}", res);
+}
+
+#[test]
+fn should_omit_end_tags_for_void_elements() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<p>One<br>Two<img src=\"x.png\"></p>")]
+    struct Breaks;
+
+    let s = weft::render_to_string(Breaks).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<p>One<br>Two<img src=\"x.png\"></p>";
+    assert!(
+        s.contains(expected),
+        "String {:?} should contain {:?}",
+        s,
+        expected
+    );
+    for unwanted in &["</br>", "</img>"] {
+        assert!(
+            !s.contains(unwanted),
+            "String {:?} should not contain {:?}",
+            s,
+            unwanted
+        );
+    }
+}
+
+#[test]
+fn should_strip_comments_by_default() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<p>Hello<!-- a comment --></p>")]
+    struct Commented;
+
+    let s = weft::render_to_string(Commented).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<p>Hello</p>");
+}
+
+#[test]
+fn should_keep_comments_when_requested() {
+    #[derive(WeftRenderable)]
+    #[template(
+        source = "<p>Hello<!--[if IE]><b>Old</b><![endif]--></p>",
+        keep_comments = true
+    )]
+    struct Commented;
+
+    let s = weft::render_to_string(Commented).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<p>Hello<!--[if IE]><b>Old</b><![endif]--></p>");
+}
+
+#[test]
+fn should_keep_leading_comments_in_fragments() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<!-- licence: MIT --><p>x</p>", keep_comments = true)]
+    struct Banner;
+
+    let s = weft::render_to_string(Banner).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<!-- licence: MIT --><p>x</p>");
+}
+
+#[test]
+fn should_strip_leading_comments_in_fragments_by_default() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<!-- licence: MIT --><p>x</p>")]
+    struct Banner;
+
+    let s = weft::render_to_string(Banner).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<p>x</p>");
+}
+
+#[test]
+fn should_write_script_literals_verbatim() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<script>if (a && b < c) { go(\"{{ self.0 }}\"); }</script>")]
+    struct Script(String);
+
+    let s = weft::render_to_string(Script("</script><b>".into())).expect("render_to_string");
+    println!("{}", s);
+
+    let expected =
+        "<script>if (a && b < c) { go(\"\\u003c\\u002fscript\\u003e\\u003cb\\u003e\"); }</script>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_write_script_numbers_verbatim() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<script>var s = \"{{ self.0 }}\"; var n = {{ self.1 }} + 1;</script>")]
+    struct Script(&'static str, i32);
+
+    let s = weft::render_to_string(Script("hi", -5)).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<script>var s = \"hi\"; var n = -5 + 1;</script>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_write_valid_json_in_scripts() {
+    #[derive(WeftRenderable)]
+    #[template(
+        source = "<script type=\"application/json\">{\"s\": \"{{ self.0 }}\", \"n\": {{ self.1 }}, \"b\": {{ self.2 }}}</script>"
+    )]
+    struct Data(&'static str, f64, bool);
+
+    let s = weft::render_to_string(Data("a\"b</script>", 1.5e-3, true)).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<script type=\"application&#x2f;json\">{\"s\": \"a\\u0022b\\u003c\\u002fscript\\u003e\", \"n\": 0.0015, \"b\": true}</script>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_write_style_literals_verbatim() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<style>p > a { content: \"{{ self.0 }}\"; }</style>")]
+    struct Style(String);
+
+    let s = weft::render_to_string(Style("a\"b".into())).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<style>p > a { content: \"a\\22 b\"; }</style>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_support_pre_escaped_content() {
+    let view = WithPolyContent {
+        child: weft::PreEscaped::dangerously_trust("<b>Bold</b>".to_string()),
+    };
+
+    let s = weft::render_to_string(view).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<p><b>Bold</b></p>";
+    assert!(
+        s.contains(expected),
+        "String {:?} should contain {:?}",
+        s,
+        expected
+    )
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<a href=\"{{ self.0 }}\">Link</a>")]
+struct Link(&'static str);
+
+#[test]
+fn should_filter_unsafe_url_schemes() {
+    let s = weft::render_to_string(Link("javascript:alert(1)")).expect("render_to_string");
+    println!("{}", s);
+
+    let unwanted = "javascript:";
+    assert!(
+        !s.contains(unwanted),
+        "String {:?} should not contain {:?}",
+        s,
+        unwanted
+    );
+}
+
+#[test]
+fn should_filter_unsafe_url_schemes_after_leading_whitespace() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<a href=\" {{ self.0 }}\">Link</a>")]
+    struct SpacedLink(&'static str);
+
+    let s = weft::render_to_string(SpacedLink("javascript:alert(1)")).expect("render_to_string");
+    println!("{}", s);
+
+    let unwanted = "javascript:";
+    assert!(
+        !s.contains(unwanted),
+        "String {:?} should not contain {:?}",
+        s,
+        unwanted
+    );
+}
+
+#[test]
+fn should_filter_unsafe_url_schemes_split_across_interpolations() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<a href=\"{{ self.0 }}:{{ self.1 }}\">Link</a>")]
+    struct SplitLink(&'static str, &'static str);
+
+    let s = weft::render_to_string(SplitLink("javascript", "alert(1)")).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<a href=\"about:invalid#weft-unsafe-url\">Link</a>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_filter_unsafe_url_schemes_completed_by_interpolation() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<a href=\"java{{ self.0 }}\">Link</a>")]
+    struct PrefixedLink(&'static str);
+
+    let s = weft::render_to_string(PrefixedLink("script:alert(1)")).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<a href=\"about:invalid#weft-unsafe-url\">Link</a>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_allow_interpolation_after_literal_scheme() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<a href=\"https://{{ self.0 }}/x?q={{ self.1 }}\">Link</a>")]
+    struct HostLink(&'static str, &'static str);
+
+    let s = weft::render_to_string(HostLink("example.com", "a:b")).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<a href=\"https:&#x2f;&#x2f;example.com&#x2f;x?q=a%3Ab\">Link</a>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_filter_unsafe_url_schemes_in_srcset() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<img srcset=\"{{ self.0 }}\">")]
+    struct Image(&'static str);
+
+    let s = weft::render_to_string(Image("javascript:alert(1)")).expect("render_to_string");
+    println!("{}", s);
+
+    let unwanted = "javascript:";
+    assert!(
+        !s.contains(unwanted),
+        "String {:?} should not contain {:?}",
+        s,
+        unwanted
+    );
+}
+
+#[test]
+fn should_encode_candidate_separators_in_srcset() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<img srcset=\"{{ self.0 }} 1x, b.png 2x\">")]
+    struct Image(&'static str);
+
+    let s = weft::render_to_string(Image("a.png 1x, evil.png")).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "srcset=\"a.png%201x%2C%20evil.png 1x, b.png 2x\"";
+    assert!(
+        s.contains(expected),
+        "String {:?} should contain {:?}",
+        s,
+        expected
+    );
+}
+
+#[test]
+fn should_allow_safe_url_schemes() {
+    let s =
+        weft::render_to_string(Link("https://example.com/a b?c=d#e")).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "example.com&#x2f;a%20b?c=d#e\"";
+    assert!(
+        s.contains(expected),
+        "String {:?} should contain {:?}",
+        s,
+        expected
+    );
+}
+
+#[test]
+fn should_allow_trusted_urls() {
+    use weft::prelude::TrustedUrl;
+    #[derive(WeftRenderable)]
+    #[template(source = "<a href=\"{{ self.0 }}\">Link</a>")]
+    struct TrustedLink(TrustedUrl<&'static str>);
+
+    let s = weft::render_to_string(TrustedLink(TrustedUrl::new("javascript:void(0)")))
+        .expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "href=\"javascript:void(0)\"";
+    assert!(
+        s.contains(expected),
+        "String {:?} should contain {:?}",
+        s,
+        expected
+    );
+}
+
+#[test]
+fn should_percent_encode_url_query_values() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<a href=\"/search?q={{ self.0 }}\">Search</a>")]
+    struct Search(String);
+
+    let s = weft::render_to_string(Search("a&b=c d".into())).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "search?q=a%26b%3Dc%20d\"";
+    assert!(
+        s.contains(expected),
+        "String {:?} should contain {:?}",
+        s,
+        expected
+    );
+}
+
+#[test]
+fn should_allow_plain_values_in_attributes_starting_with_on() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<p one=\"{{ self.0 }}\" only=\"{{ self.0 }}\">Hi</p>")]
+    struct NotHandlers(&'static str);
+
+    let s = weft::render_to_string(NotHandlers("x")).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<p one=\"x\" only=\"x\">Hi</p>");
+}
+
+#[test]
+fn should_encode_js_strings_in_event_handlers() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<button onclick=\"greet({{ JsString::new(&self.0) }})\">Hi</button>")]
+    struct Button(String);
+
+    let s = weft::render_to_string(Button("\"); alert(1); (\"".into())).expect("render_to_string");
+    println!("{}", s);
+
+    let expected =
+        "onclick=\"greet(&quot;\\u0022\\u0029\\u003b\\u0020alert\\u00281\\u0029\\u003b\\u0020\\u0028\\u0022&quot;)\"";
+    assert!(
+        s.contains(expected),
+        "String {:?} should contain {:?}",
+        s,
+        expected
+    );
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<input type=\"checkbox\" checked=\"{{ self.0 }}\" class=\"box\">")]
+struct Checkbox(bool);
+
+#[test]
+fn should_include_boolean_attributes_when_true() {
+    let s = weft::render_to_string(Checkbox(true)).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<input checked class=\"box\" type=\"checkbox\">";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_omit_boolean_attributes_when_false() {
+    let s = weft::render_to_string(Checkbox(false)).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<input class=\"box\" type=\"checkbox\">";
+    assert_eq!(s, expected);
+}
+
+#[derive(WeftRenderable)]
+#[template(
+    source = "<div class=\"card\" id=\"x\" weft-attrs=\"self.0.iter().cloned()\">Card</div>"
+)]
+struct DynamicAttrs(Vec<(&'static str, &'static str)>);
+
+#[test]
+fn should_merge_dynamic_attributes() {
+    let view = DynamicAttrs(vec![("data-id", "42"), ("aria-label", "A card")]);
+    let s = weft::render_to_string(view).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<div class=\"card\" id=\"x\" data-id=\"42\" aria-label=\"A card\">";
+    assert!(
+        s.contains(expected),
+        "String {:?} should contain {:?}",
+        s,
+        expected
+    );
+}
+
+#[test]
+fn should_concatenate_dynamic_classes_and_replace_others() {
+    let view = DynamicAttrs(vec![("class", "active"), ("id", "y")]);
+    let s = weft::render_to_string(view).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<div class=\"card active\" id=\"y\">";
+    assert!(
+        s.contains(expected),
+        "String {:?} should contain {:?}",
+        s,
+        expected
+    );
+}
+
+#[test]
+fn should_reject_dynamic_event_handlers() {
+    let view = DynamicAttrs(vec![("onclick", "alert(1)")]);
+    let res = weft::render_to_string(view);
+    assert!(res.is_err(), "Rendering should fail; got: {:?}", res);
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<div>
+  <p weft-if=\"self.0 > 10\">Many</p>
+  <p weft-else-if=\"self.0 > 0\">Some</p>
+  <p weft-else>None</p>
+</div>")]
+struct Quantity(usize);
+
+#[test]
+fn should_render_if_branch() {
+    let s = weft::render_to_string(Quantity(42)).expect("render_to_string");
+    println!("{}", s);
+
+    assert!(
+        s.contains("<p>Many</p>"),
+        "String {:?} should contain Many",
+        s
+    );
+    for unexpected in &["Some", "None"] {
+        assert!(
+            !s.contains(unexpected),
+            "String {:?} should not contain {:?}",
+            s,
+            unexpected
+        );
+    }
+}
+
+#[test]
+fn should_render_else_if_branch() {
+    let s = weft::render_to_string(Quantity(3)).expect("render_to_string");
+    println!("{}", s);
+
+    assert!(
+        s.contains("<p>Some</p>"),
+        "String {:?} should contain Some",
+        s
+    );
+    for unexpected in &["Many", "None"] {
+        assert!(
+            !s.contains(unexpected),
+            "String {:?} should not contain {:?}",
+            s,
+            unexpected
+        );
+    }
+}
+
+#[test]
+fn should_render_else_branch() {
+    let s = weft::render_to_string(Quantity(0)).expect("render_to_string");
+    println!("{}", s);
+
+    assert!(
+        s.contains("<p>None</p>"),
+        "String {:?} should contain None",
+        s
+    );
+    for unexpected in &["Many", "Some"] {
+        assert!(
+            !s.contains(unexpected),
+            "String {:?} should not contain {:?}",
+            s,
+            unexpected
+        );
+    }
+}
+
+enum PageState {
+    Loading,
+    Loaded(Vec<&'static str>),
+    Failed { reason: String },
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<div weft-match=\"&self.0\">
+  <p weft-case=\"PageState::Loading\">Loading…</p>
+  <ul weft-case=\"PageState::Loaded(items)\"><li weft-for=\"item in items\">{{ item }}</li></ul>
+  <p weft-case=\"PageState::Failed { reason }\" class=\"error\">{{ reason }}</p>
+</div>")]
+struct Page(PageState);
+
+#[test]
+fn should_render_matching_case() {
+    let s = weft::render_to_string(Page(PageState::Loading)).expect("render_to_string");
+    println!("{}", s);
+    assert_eq!(s, "<div><p>Loading…</p></div>");
+
+    let s =
+        weft::render_to_string(Page(PageState::Loaded(vec!["a", "b"]))).expect("render_to_string");
+    println!("{}", s);
+    assert_eq!(s, "<div><ul><li>a</li><li>b</li></ul></div>");
+
+    let s = weft::render_to_string(Page(PageState::Failed {
+        reason: "Oops".into(),
+    }))
+    .expect("render_to_string");
+    println!("{}", s);
+    assert_eq!(s, "<div><p class=\"error\">Oops</p></div>");
+}
+
+#[test]
+fn should_support_fallback_and_guards_in_match() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<span weft-match=\"self.0\">\
+        <b weft-case=\"n if n > 100\">Lots</b>\
+        <i weft-case=\"0\">Nothing</i>\
+        <em weft-case=\"_\">Some</em>\
+    </span>")]
+    struct Count(u32);
+
+    let s = weft::render_to_string(Count(101)).expect("render_to_string");
+    assert_eq!(s, "<span><b>Lots</b></span>");
+    let s = weft::render_to_string(Count(0)).expect("render_to_string");
+    assert_eq!(s, "<span><i>Nothing</i></span>");
+    let s = weft::render_to_string(Count(7)).expect("render_to_string");
+    assert_eq!(s, "<span><em>Some</em></span>");
+}
+
+#[test]
+fn should_support_let_bindings() {
+    #[derive(WeftRenderable)]
+    #[template(
+        source = "<div weft-let=\"year = self.0.display(); next = self.0 + 1\" title=\"{{ year }}\">\
+        <h1>CSL {{ year }}</h1><p>Next: {{ next.display() }}</p>\
+    </div>"
+    )]
+    struct Season(u16);
+
+    let s = weft::render_to_string(Season(2015)).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<div title=\"2015\"><h1>CSL 2015</h1><p>Next: 2016</p></div>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_evaluate_let_bindings_per_iteration() {
+    #[derive(WeftRenderable)]
+    #[template(
+        source = "<ul><li weft-for=\"n in self.0.iter()\" weft-let=\"sq = n * n\">{{ sq.display() }}</li></ul>"
+    )]
+    struct Squares(Vec<u32>);
+
+    let s = weft::render_to_string(Squares(vec![1, 2, 3])).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<ul><li>1</li><li>4</li><li>9</li></ul>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_support_loop_info() {
+    #[derive(WeftRenderable)]
+    #[template(
+        source = "<ol><li weft-for=\"item in self.0.iter()\" weft-loop=\"info\" \
+        class='{{ if info.first() { \"first\" } else if info.last() { \"last\" } else if info.odd() { \"odd\" } else { \"even\" } }}'\
+        >{{ info.index1().display() }} of {{ info.length().unwrap_or(0).display() }} {{ item }}</li></ol>"
+    )]
+    struct Listing(Vec<&'static str>);
+
+    let s = weft::render_to_string(Listing(vec!["a", "b", "c", "d"])).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<ol>\
+        <li class=\"first\">1 of 4 a</li>\
+        <li class=\"odd\">2 of 4 b</li>\
+        <li class=\"even\">3 of 4 c</li>\
+        <li class=\"last\">4 of 4 d</li>\
+        </ol>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_find_last_item_without_known_length() {
+    #[derive(WeftRenderable)]
+    #[template(
+        source = "<p><span weft-for=\"n in self.0.iter().filter(|n| **n > 1)\" weft-loop=\"info\" \
+        class='{{ if info.last() { \"last\" } else { \"\" } }}'>{{ n.display() }}</span></p>"
+    )]
+    struct Filtered(Vec<u32>);
+
+    let s = weft::render_to_string(Filtered(vec![1, 2, 3])).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<p><span class>2</span><span class=\"last\">3</span></p>";
+    assert_eq!(s, expected);
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<ul>
+  <li weft-for=\"item in self.0.iter()\">{{ item }}</li>
+  <li weft-for-empty class=\"empty\">No results</li>
+</ul>")]
+struct Results(Vec<&'static str>);
+
+#[test]
+fn should_render_empty_fallback_for_empty_iterator() {
+    let s = weft::render_to_string(Results(vec![])).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<li class=\"empty\">No results</li>";
+    assert!(
+        s.contains(expected),
+        "String {:?} should contain {:?}",
+        s,
+        expected
+    );
+}
+
+#[test]
+fn should_not_render_empty_fallback_for_non_empty_iterator() {
+    let s = weft::render_to_string(Results(vec!["one"])).expect("render_to_string");
+    println!("{}", s);
+
+    assert!(
+        s.contains("<li>one</li>"),
+        "String {:?} should contain item",
+        s
+    );
+    let unexpected = "No results";
+    assert!(
+        !s.contains(unexpected),
+        "String {:?} should not contain {:?}",
+        s,
+        unexpected
+    );
+}
+
+#[test]
+fn should_strip_element_with_weft_strip() {
+    #[derive(WeftRenderable)]
+    #[template(
+        source = "<section><div class=\"wrapper\" weft-strip><p>One</p><p>Two</p></div></section>"
+    )]
+    struct Stripped;
+
+    let s = weft::render_to_string(Stripped).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<section><p>One</p><p>Two</p></section>");
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<p><a href=\"/profile\" weft-strip=\"!self.0\">Profile</a></p>")]
+struct MaybeLink(bool);
+
+#[test]
+fn should_strip_element_when_condition_true() {
+    let s = weft::render_to_string(MaybeLink(false)).expect("render_to_string");
+    println!("{}", s);
+    assert_eq!(s, "<p>Profile</p>");
+}
+
+#[test]
+fn should_keep_element_when_strip_condition_false() {
+    let s = weft::render_to_string(MaybeLink(true)).expect("render_to_string");
+    println!("{}", s);
+    let expected = ">Profile</a></p>";
+    assert!(
+        s.starts_with("<p><a href=") && s.ends_with(expected),
+        "String {:?} should contain a link",
+        s
+    );
+}
+
+#[test]
+fn should_never_render_block_pseudo_element() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<dl><weft:block weft-for=\"(term, def) in self.0.iter()\">\
+        <dt>{{ term }}</dt><dd>{{ def }}</dd>\
+    </weft:block></dl>")]
+    struct Definitions(Vec<(&'static str, &'static str)>);
+
+    let s = weft::render_to_string(Definitions(vec![("a", "Apple"), ("b", "Banana")]))
+        .expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(
+        s,
+        "<dl><dt>a</dt><dd>Apple</dd><dt>b</dt><dd>Banana</dd></dl>"
+    );
+}
+
+#[test]
+fn should_disable_inline_exprs_in_descendants_and_attrs() {
+    #[derive(WeftRenderable)]
+    #[template(
+        source = "<div weft-inline-disable><span title=\"{{ x }}\">{{ y }}</span></div><p>{{ self.0 }}</p>"
+    )]
+    struct Vue(&'static str);
+
+    let s = weft::render_to_string(Vue("enabled")).expect("render_to_string");
+    println!("{}", s);
+
+    let expected = "<div><span title=\"{{ x }}\">{{ y }}</span></div><p>enabled</p>";
+    assert_eq!(s, expected);
+}
+
+#[test]
+fn should_support_escaped_inline_open() {
+    #[derive(WeftRenderable)]
+    #[template(source = "<p>Write \\{{ name }} to show {{ self.0 }}</p>")]
+    struct Docs(&'static str);
+
+    let s = weft::render_to_string(Docs("Bob")).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<p>Write {{ name }} to show Bob</p>");
+}
+
+#[derive(WeftRenderable)]
+#[template(path = "tests/fragments.html", def = "Header")]
+struct Header {
+    title: &'static str,
+}
+
+#[derive(WeftRenderable)]
+#[template(path = "tests/fragments.html", selector = ".items li", nth = 1)]
+struct SecondItem;
+
+#[derive(WeftRenderable)]
+#[template(path = "tests/fragments.html", def = "Row")]
+struct TableRow(&'static str);
+
+#[test]
+fn should_derive_from_named_fragment() {
+    let s = weft::render_to_string(Header { title: "Hello" }).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<h1>Hello</h1>");
+}
+
+#[test]
+fn should_derive_table_rows_from_named_fragment() {
+    let s = weft::render_to_string(TableRow("Cell")).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<tr><td>Cell</td></tr>");
+}
+
+#[test]
+fn should_derive_from_nth_match() {
+    let s = weft::render_to_string(SecondItem).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "Second");
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<p>Next: {{ (self.parsed()? + 1).display() }}</p>")]
+struct Incremented(&'static str);
+
+impl Incremented {
+    fn parsed(&self) -> Result<u32, std::num::ParseIntError> {
+        self.0.parse()
+    }
+}
+
+#[test]
+fn should_allow_fallible_expressions() {
+    let s = weft::render_to_string(Incremented("41")).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<p>Next: 42</p>");
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<p title='{{ self.parsed()? * 2 }}'></p>")]
+struct Doubled(&'static str);
+
+impl Doubled {
+    fn parsed(&self) -> Result<u32, std::num::ParseIntError> {
+        self.0.parse()
+    }
+}
+
+#[test]
+fn should_allow_fallible_expressions_in_attributes() {
+    let s = weft::render_to_string(Doubled("21")).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<p title=\"42\"></p>");
+}
+
+#[test]
+fn should_return_errors_from_fallible_expressions() {
+    let err = weft::render_to_string(Incremented("forty-one")).expect_err("should fail");
+    println!("{}", err);
+
+    assert_eq!(err.kind(), weft::ErrorKind::Render);
+    assert!(
+        err.downcast_ref::<std::num::ParseIntError>().is_some(),
+        "Error should be a ParseIntError: {:?}",
+        err
+    );
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<span>{{ self.parsed()?.display() }}</span>")]
+struct Row(&'static str);
+
+impl Row {
+    fn parsed(&self) -> Result<u32, std::num::ParseIntError> {
+        self.0.parse()
+    }
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<ul><li weft-for='row in self.0.iter()' weft-content='row'></li></ul>")]
+struct Rows(Vec<Row>);
+
+#[test]
+fn should_report_where_rendering_failed() {
+    let rows = Rows(vec![Row("1"), Row("2"), Row("three")]);
+    let err = weft::render_to_string(rows).expect_err("should fail");
+    println!("{}", err);
+
+    assert_eq!(err.templates(), &["Row", "Rows"]);
+    assert_eq!(err.element_path(), Some("ul > li[3] > span"));
+    assert_eq!(
+        err.to_string(),
+        "Rendering failed: invalid digit found in string in Row within Rows at ul > li[3] > span"
+    );
+}
+
+#[derive(WeftRenderable)]
+#[template(source = "<span>{{ self.0 }}</span><span>{{ self.1 }}</span>")]
+struct Cells(u32, f64);
+
+#[test]
+fn should_render_numbers_without_display() {
+    let s = weft::render_to_string(Cells(3, 0.25)).expect("render_to_string");
+    println!("{}", s);
+
+    assert_eq!(s, "<span>3</span><span>0.25</span>");
+}